@@ -4,13 +4,17 @@ import {
   Transaction,
   Keypair,
   SystemProgram,
-  SYSVAR_CLOCK_PUBKEY
+  SYSVAR_CLOCK_PUBKEY,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  TransactionInstruction
 } from '@solana/web3.js';
+import { randomBytes } from 'crypto';
 import { BN, Program, Provider, web3, AnchorProvider } from '@coral-xyz/anchor';
 import { TradingDuelProtocol } from '../../target/types/trading_duel_protocol';
 import idl from '../../target/idl/trading_duel_protocol.json';
 
 export const TRADING_DUEL_PROGRAM_ID = new PublicKey('2tjZvgNNXxGhHm6dzQx65rbVbEb8ZtJRN95gcgeE8bo8');
+export const CLIENT_VERSION = 1;
 const IDL = idl as TradingDuelProtocol;
 
 export enum DuelStatus {
@@ -18,7 +22,10 @@ export enum DuelStatus {
   Accepted = 'Accepted', 
   Active = 'Active',
   Settled = 'Settled',
-  Cancelled = 'Cancelled'
+  Cancelled = 'Cancelled',
+  Locked = 'Locked',
+  Paused = 'Paused',
+  PendingOracle = 'PendingOracle'
 }
 
 export enum DuelWinner {
@@ -28,13 +35,48 @@ export enum DuelWinner {
   Draw = 'Draw'
 }
 
+export enum DuelCategory {
+  Meme = 'meme',
+  BlueChip = 'blueChip',
+  Defi = 'defi',
+  Nft = 'nft',
+  Custom = 'custom'
+}
+
 export interface CreateDuelParams {
   stakeAmount: BN;
   durationSeconds: BN;
   allowedTokens: PublicKey[];
+  category?: DuelCategory;
+  tokenAllocation?: { mint: PublicKey; maxWeightBps: number }[];
+  // Omit to compare PnL at expiry; FirstToTarget settles once either side reaches the target.
+  // Short HighestPnl duels are settled as HigherAbsoluteReturn (see absolutePnlDurationThreshold).
+  winCondition?:
+    | { highestPnl: {} }
+    | { firstToTarget: { targetPnlBps: BN } }
+    | { higherAbsoluteReturn: {} };
+  // Hold stakes as wrapped SOL; the create_wsol_escrow instruction must run before deposits
+  useWsol?: boolean;
+  // ManualOracle duels have no end time; they end on the oracle's final update
+  expiryMode?: { timeBased: {} } | { manualOracle: {} };
+  // Required to list the native SOL mint in allowedTokens
+  usesSolNatively?: boolean;
+  // Duel PDA seed; a random one is generated when omitted
+  nonce?: BN;
   opponent?: PublicKey;
 }
 
+// Signed DEX trade backing a position update. The Ed25519 signature covers
+// duel || token || tradeAmount (u64 LE) || timestamp (i64 LE), and tradePubkey
+// must be one of the protocol's registered trade attestors.
+export interface TradeProof {
+  tradeSignature: number[];
+  tradePubkey: number[];
+  tradeAmount: BN;
+  token: PublicKey;
+  timestamp: BN;
+}
+
 export interface DuelAccount {
   creator: PublicKey;
   opponent: PublicKey;
@@ -72,9 +114,22 @@ export class TradingDuelClient {
   }
 
   // Get duel PDA
-  getDuelAddress(duelIndex: number): [PublicKey, number] {
+  getDuelAddress(creator: PublicKey, nonce: BN): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from('duel'), creator.toBuffer(), nonce.toArrayLike(Buffer, 'le', 8)],
+      TRADING_DUEL_PROGRAM_ID
+    );
+  }
+
+  // Get ranked duel PDA (seeded by the joining player and their nonce)
+  getRankedDuelAddress(player: PublicKey, nonce: BN): [PublicKey, number] {
+    return this.getDuelAddress(player, nonce);
+  }
+
+  // Get pool duel PDA (one per pool pairing)
+  getPoolDuelAddress(pool: PublicKey, i: number, j: number): [PublicKey, number] {
     return PublicKey.findProgramAddressSync(
-      [Buffer.from('duel'), new BN(duelIndex).toArrayLike(Buffer, 'le', 8)],
+      [Buffer.from('pool_duel'), pool.toBuffer(), Buffer.from([i]), Buffer.from([j])],
       TRADING_DUEL_PROGRAM_ID
     );
   }
@@ -87,6 +142,14 @@ export class TradingDuelClient {
     );
   }
 
+  // Get duel timer PDA
+  getTimerAddress(duelPubkey: PublicKey): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from('timer'), duelPubkey.toBuffer()],
+      TRADING_DUEL_PROGRAM_ID
+    );
+  }
+
   // Initialize the protocol (admin only)
   async initialize(
     authority: Keypair,
@@ -116,20 +179,31 @@ export class TradingDuelClient {
   ): Promise<{ duelPubkey: PublicKey; signature: string }> {
     const [protocolPda] = this.getProtocolAddress();
     const protocol = await this.program.account.protocol.fetch(protocolPda);
-    const duelIndex = protocol.totalDuels.toNumber();
+    const nonce = params.nonce ?? new BN(randomBytes(8), 'le');
     
-    const [duelPda] = this.getDuelAddress(duelIndex);
+    const [duelPda] = this.getDuelAddress(creator.publicKey, nonce);
 
     const tx = await this.program.methods
       .createDuel(
+        nonce,
         params.stakeAmount,
         params.durationSeconds,
-        params.allowedTokens
+        params.allowedTokens,
+        {
+          category: { [params.category ?? DuelCategory.Custom]: {} } as any,
+          clientVersion: CLIENT_VERSION,
+          creatorTokenAllocation: params.tokenAllocation ?? [],
+          winCondition: params.winCondition ?? { highestPnl: {} },
+          useWsol: params.useWsol ?? false,
+          expiryMode: params.expiryMode ?? { timeBased: {} },
+          duelUsesSolNatively: params.usesSolNatively ?? false,
+        }
       )
       .accounts({
         duel: duelPda,
         protocol: protocolPda,
         creator: creator.publicKey,
+        treasury: protocol.treasury,
         systemProgram: SystemProgram.programId,
       })
       .signers([creator])
@@ -161,6 +235,7 @@ export class TradingDuelClient {
     duelPubkey: PublicKey
   ): Promise<string> {
     const [escrowPda] = this.getEscrowAddress(duelPubkey);
+    const [timerPda] = this.getTimerAddress(duelPubkey);
 
     const tx = await this.program.methods
       .depositStake()
@@ -169,6 +244,7 @@ export class TradingDuelClient {
         duelEscrow: escrowPda,
         depositor: depositor.publicKey,
         systemProgram: SystemProgram.programId,
+        duelTimer: timerPda,
       })
       .signers([depositor])
       .rpc();
@@ -176,19 +252,41 @@ export class TradingDuelClient {
     return tx;
   }
 
-  // Update positions (oracle only)
+  // Update positions (authorized oracles only). `tradeProofIx` is the Ed25519 program
+  // instruction verifying `tradeProof`; it must run immediately before.
+  // `oracleSignatureIx` verifies the oracle's Ed25519 signature over
+  // sha256(duel || creatorValue (u64 LE) || opponentValue (u64 LE) || oracleTimestamp (i64 LE))
+  // and must run just ahead of the trade proof.
+  // Pass `isFinal` to end trading on a manually expiring duel.
   async updatePositions(
     oracle: Keypair,
     duelPubkey: PublicKey,
     creatorValue: BN,
-    opponentValue: BN
+    opponentValue: BN,
+    tradeProof: TradeProof,
+    tradeProofIx: TransactionInstruction,
+    oracleSignature: number[],
+    oracleTimestamp: BN,
+    oracleSignatureIx: TransactionInstruction,
+    creatorAllocation: { mint: PublicKey; value: BN }[] = [],
+    opponentAllocation: { mint: PublicKey; value: BN }[] = [],
+    isFinal = false
   ): Promise<string> {
     const tx = await this.program.methods
-      .updatePositions(creatorValue, opponentValue)
+      .updatePositions(creatorValue, opponentValue, {
+        creatorAllocation,
+        opponentAllocation,
+        tradeProof,
+        isFinal,
+        oracleSignature,
+        oracleTimestamp,
+      })
       .accounts({
         duel: duelPubkey,
         oracle: oracle.publicKey,
+        instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
       })
+      .preInstructions([oracleSignatureIx, tradeProofIx])
       .signers([oracle])
       .rpc();
 
@@ -231,6 +329,8 @@ export class TradingDuelClient {
       .accounts({
         duel: duelPubkey,
         creator: creator.publicKey,
+        opponent: null,
+        canceller: creator.publicKey,
       })
       .signers([creator])
       .rpc();
@@ -1,8 +1,164 @@
+// `#[program]` expands to `AccountInfo::realloc`, deprecated in solana-program 2.x
+#![allow(deprecated)]
+
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::clock::Clock;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_spl::token::{self, Mint, Token, TokenAccount};
 
 declare_id!("2tjZvgNNXxGhHm6dzQx65rbVbEb8ZtJRN95gcgeE8bo8");
 
+// Program version reported by protocol_health_check
+pub const PROGRAM_VERSION: u32 = 1;
+
+// Default duel duration bounds (seconds), adjustable by the protocol authority
+pub const DEFAULT_MIN_DUEL_DURATION: i64 = 0;
+pub const DEFAULT_MAX_DUEL_DURATION: i64 = 30 * 24 * 60 * 60;
+
+// Bit positions in `Protocol::paused_instructions`. cancel_duel is deliberately
+// not pausable so participants can always back out of a duel.
+pub const IX_CREATE_DUEL: u8 = 0;
+pub const IX_ACCEPT_DUEL: u8 = 1;
+pub const IX_DEPOSIT_STAKE: u8 = 2;
+pub const IX_UPDATE_POSITIONS: u8 = 3;
+pub const IX_SETTLE_DUEL: u8 = 4;
+
+// Batch settlement limits (tuned for the default compute budget)
+pub const MAX_BULK_SETTLE: usize = 5;
+pub const BULK_SETTLE_GROUP_SIZE: usize = 6;
+
+// Distinct oracles tracked per duel, and how often their counts are emitted
+pub const MAX_DUEL_ORACLES: usize = 5;
+pub const ORACLE_UPDATE_EVENT_INTERVAL: u32 = 10;
+
+// Oracle keys the authority may authorize to report positions
+pub const MAX_AUTHORIZED_ORACLES: usize = 5;
+
+// Keys the authority may register to sign DEX trade proofs
+pub const MAX_TRADE_ATTESTORS: usize = 5;
+
+// Maximum length of a compliance notice attached to a duel
+pub const DUEL_NOTICE_MAX_LEN: usize = 512;
+
+// Per-duel forum limits, sized to keep the forum under the 10KB account
+// creation limit
+pub const FORUM_COMMENT_MAX_LEN: usize = 280;
+pub const MAX_FORUM_COMMENTS: usize = 30;
+
+// Fallback token list used when a duel is created without allowed tokens
+pub const MAX_DEFAULT_ALLOWED_TOKENS: usize = 5;
+
+// Rating assigned to new player profiles
+pub const DEFAULT_ELO_RATING: u32 = 1200;
+
+// How long per-duel settlement records must be kept before they can be
+// closed, unless the creator closes them with the duel in settle_and_close_duel
+pub const SETTLEMENT_RECORD_RETENTION_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+// Ranked matchmaking queue settings
+pub const MAX_RANKED_QUEUE_ENTRIES: usize = 20;
+pub const DEFAULT_ELO_MATCH_RANGE: u32 = 200;
+pub const DEFAULT_QUEUE_TIMEOUT_SECONDS: i64 = 60 * 60;
+pub const RANKED_DUEL_DURATION: i64 = 24 * 60 * 60;
+
+// Per-token portfolio weight limits a creator can attach to a duel
+pub const MAX_TOKEN_ALLOCATION_LIMITS: usize = 10;
+
+// Tokens a saved duel template can list
+pub const MAX_TEMPLATE_ALLOWED_TOKENS: usize = 10;
+
+// Price feed entries in a duel's price index (one per allowed token)
+pub const MAX_PRICE_FEEDS: usize = 10;
+
+// Allowed tokens a duel account has room for at creation; add_allowed_token
+// reallocs past this, up to the protocol's max_allowed_tokens
+pub const DUEL_RESERVED_ALLOWED_TOKENS: usize = 10;
+pub const DEFAULT_MAX_ALLOWED_TOKENS: u8 = 20;
+
+// Valid Duel account sizes: as created, up to fully grown by add_allowed_token
+pub const MIN_DUEL_SIZE: usize = Duel::space();
+pub const MAX_DUEL_SIZE: usize =
+    Duel::space() + 32 * (u8::MAX as usize - DUEL_RESERVED_ALLOWED_TOKENS);
+
+// Winners with at least this many settled duels pay a reduced fee
+pub const DEFAULT_LOYALTY_DISCOUNT_THRESHOLD: u32 = 10;
+pub const DEFAULT_LOYALTY_DISCOUNT_BPS: u16 = 50;
+
+// Reputation = elo * w[0] + win_streak * w[1] + total_volume / w[2]
+pub const DEFAULT_REPUTATION_WEIGHTS: [u64; 3] = [100, 500, 1_000_000];
+
+// Oracle update cadence used to size a duel's required_oracle_updates
+pub const DEFAULT_MIN_UPDATE_INTERVAL_SECONDS: i64 = 60 * 60;
+
+// HighestPnl duels shorter than this are decided on absolute return instead
+pub const DEFAULT_ABSOLUTE_PNL_DURATION_THRESHOLD: i64 = 60 * 60;
+
+// Creators whose duel is accepted within this window earn the quick-accept bonus
+pub const QUICK_ACCEPT_WINDOW_SECONDS: i64 = 60 * 60;
+
+// Sanity bounds for the cluster clock (2023-11-14 to 2033-05-18)
+pub const MIN_VALID_TIMESTAMP: i64 = 1_700_000_000;
+pub const MAX_VALID_TIMESTAMP: i64 = 2_000_000_000;
+
+// Friend list capacity per player
+pub const MAX_FRIENDS: usize = 50;
+pub const MAX_PENDING_FRIEND_REQUESTS: usize = 20;
+
+// Largest move allowed between consecutive position updates (100_000 bps = 10x)
+pub const DEFAULT_MAX_UPDATE_VALUE_DELTA_BPS: u32 = 100_000;
+
+// Entries kept in a duel's audit log before the oldest is overwritten (an
+// account created by CPI is capped at 10KB)
+pub const MAX_AUDIT_ENTRIES: usize = 90;
+
+// Players ranked on each per-category leaderboard
+pub const CATEGORY_LEADERBOARD_SIZE: usize = 10;
+
+// Activity heatmap buckets: one per hour of the week (bucket 0 is Thursday 00:00 UTC)
+pub const HEATMAP_BUCKETS: usize = 24 * 7;
+
+// Delay between proposing and confirming a treasury change
+pub const TREASURY_CHANGE_TIMELOCK_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+// Round-robin pool limits: every participant duels every other participant
+pub const MAX_POOL_PARTICIPANTS: usize = 8;
+pub const MAX_POOL_DUELS: usize = MAX_POOL_PARTICIPANTS * (MAX_POOL_PARTICIPANTS - 1) / 2;
+
+// How long a funded duel may wait for the oracle's starting values before
+// anyone can cancel it and refund both stakes
+pub const DEFAULT_ORACLE_INIT_DEADLINE_SECONDS: i64 = 60 * 60;
+
+// Approximate settle_duel compute costs by path, used by estimate_settle_compute.
+// Re-profile (see the ComputeUsed events) when settlement changes.
+pub const BASE_SETTLE_CU: u64 = 15_000;
+pub const DRAW_EXTRA_CU: u64 = 3_000;
+pub const WSOL_UNWRAP_EXTRA_CU: u64 = 6_000;
+pub const CATEGORY_LEADERBOARD_EXTRA_CU: u64 = 4_000;
+
+// Compute unit telemetry for heavy instructions. `log_compute!()` logs and
+// returns the remaining budget at the start of an instruction;
+// `log_compute!("name", start)` logs again and emits a ComputeUsed event
+// with the units consumed since `start`.
+macro_rules! log_compute {
+    () => {{
+        anchor_lang::solana_program::log::sol_log_compute_units();
+        anchor_lang::solana_program::compute_units::sol_remaining_compute_units()
+    }};
+    ($instruction_name:expr, $start:expr) => {{
+        anchor_lang::solana_program::log::sol_log_compute_units();
+        let remaining = anchor_lang::solana_program::compute_units::sol_remaining_compute_units();
+        emit!(ComputeUsed {
+            instruction_name: to_instruction_name($instruction_name),
+            compute_units: $start.saturating_sub(remaining),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+    }};
+}
+
 #[program]
 pub mod trading_duel_protocol {
     use super::*;
@@ -15,463 +171,6898 @@ pub mod trading_duel_protocol {
         protocol.fee_bps = protocol_fee_bps;
         protocol.total_duels = 0;
         protocol.total_volume = 0;
+        protocol.min_duel_duration = DEFAULT_MIN_DUEL_DURATION;
+        protocol.max_duel_duration = DEFAULT_MAX_DUEL_DURATION;
+        protocol.paused_instructions = 0;
+        protocol.stake_lock_period_seconds = 0;
+        protocol.category_enabled = [true; DUEL_CATEGORY_COUNT];
+        protocol.min_client_version = 0;
+        protocol.default_allowed_tokens = Vec::new();
+        protocol.ranked_mode_enabled = false;
+        protocol.ranked_min_stake_factor = 0;
+        protocol.elo_match_range = DEFAULT_ELO_MATCH_RANGE;
+        protocol.queue_timeout_seconds = DEFAULT_QUEUE_TIMEOUT_SECONDS;
+        protocol.creator_quick_accept_bonus_bps = 0;
+        protocol.bonus_condition = QuickAcceptBonusCondition::Always;
+        protocol.whitepaper_hash = [0; 32];
+        protocol.terms_hash = [0; 32];
+        protocol.max_update_value_delta_bps = DEFAULT_MAX_UPDATE_VALUE_DELTA_BPS;
+        protocol.creation_fee_lamports = 0;
+        protocol.total_fees_collected = 0;
+        protocol.total_pools = 0;
+        protocol.pending_treasury = Pubkey::default();
+        protocol.treasury_change_proposed_at = 0;
+        protocol.max_deposit_gap_seconds = 0;
+        protocol.oracle_init_deadline_seconds = DEFAULT_ORACLE_INIT_DEADLINE_SECONDS;
+        protocol.max_manual_duration = DEFAULT_MAX_DUEL_DURATION;
+        protocol.total_active_stake = 0;
+        protocol.peak_active_stake = 0;
+        protocol.loyalty_discount_threshold = DEFAULT_LOYALTY_DISCOUNT_THRESHOLD;
+        protocol.loyalty_discount_bps = DEFAULT_LOYALTY_DISCOUNT_BPS;
+        protocol.reputation_weights = DEFAULT_REPUTATION_WEIGHTS;
+        protocol.total_fee_proposals = 0;
+        protocol.absolute_pnl_duration_threshold = DEFAULT_ABSOLUTE_PNL_DURATION_THRESHOLD;
+        protocol.max_allowed_tokens = DEFAULT_MAX_ALLOWED_TOKENS;
+        protocol.min_update_interval_seconds = DEFAULT_MIN_UPDATE_INTERVAL_SECONDS;
+        protocol.require_profile_for_participation = false;
+        protocol.initialized = true;
+        protocol.authorized_oracles = Vec::new();
+        protocol.trade_attestors = Vec::new();
+        protocol.pending_duels = 0;
+        protocol.accepted_duels = 0;
         Ok(())
     }
 
-    // Create a new duel challenge
-    pub fn create_duel(
-        ctx: Context<CreateDuel>,
-        stake_amount: u64,
+    // Set the keys that sign the DEX trade proofs backing position updates
+    pub fn set_trade_attestors(ctx: Context<UpdateProtocol>, attestors: Vec<Pubkey>) -> Result<()> {
+        require!(
+            attestors.len() <= MAX_TRADE_ATTESTORS,
+            DuelError::TooManyTradeAttestors
+        );
+        let mut attestors = attestors;
+        attestors.sort();
+        attestors.dedup();
+        ctx.accounts.protocol.trade_attestors = attestors;
+        Ok(())
+    }
+
+    // Set the oracle keys allowed to report positions and sign position values
+    pub fn set_authorized_oracles(ctx: Context<UpdateProtocol>, oracles: Vec<Pubkey>) -> Result<()> {
+        require!(
+            oracles.len() <= MAX_AUTHORIZED_ORACLES,
+            DuelError::TooManyAuthorizedOracles
+        );
+        let mut oracles = oracles;
+        oracles.sort();
+        oracles.dedup();
+        ctx.accounts.protocol.authorized_oracles = oracles;
+        Ok(())
+    }
+
+    // Require creators and opponents to have a PlayerProfile
+    pub fn update_require_profile(ctx: Context<UpdateProtocol>, required: bool) -> Result<()> {
+        ctx.accounts.protocol.require_profile_for_participation = required;
+        emit!(ParticipationRequirementChanged { required });
+        Ok(())
+    }
+
+    // Set how many tokens add_allowed_token may grow a duel's list to
+    pub fn update_max_allowed_tokens(
+        ctx: Context<UpdateProtocol>,
+        max_allowed_tokens: u8,
+    ) -> Result<()> {
+        ctx.accounts.protocol.max_allowed_tokens = max_allowed_tokens;
+        Ok(())
+    }
+
+    // Set the duration below which HighestPnl duels compare absolute returns
+    pub fn update_absolute_pnl_duration_threshold(
+        ctx: Context<UpdateProtocol>,
+        absolute_pnl_duration_threshold: i64,
+    ) -> Result<()> {
+        require!(absolute_pnl_duration_threshold >= 0, DuelError::InvalidDurationBounds);
+        ctx.accounts.protocol.absolute_pnl_duration_threshold = absolute_pnl_duration_threshold;
+        Ok(())
+    }
+
+    // Set the reputation score weights: ELO and win-streak multipliers, and
+    // the lamport volume divisor
+    pub fn update_reputation_weights(
+        ctx: Context<UpdateProtocol>,
+        reputation_weights: [u64; 3],
+    ) -> Result<()> {
+        require!(reputation_weights[2] > 0, DuelError::InvalidReputationWeights);
+        ctx.accounts.protocol.reputation_weights = reputation_weights;
+        Ok(())
+    }
+
+    // Propose a new protocol fee; profile holders vote on it until the
+    // voting period ends
+    pub fn create_fee_proposal(
+        ctx: Context<CreateFeeProposal>,
+        fee_bps: u16,
         duration_seconds: i64,
-        allowed_tokens: Vec<Pubkey>,
     ) -> Result<()> {
-        let duel = &mut ctx.accounts.duel;
-        let clock = Clock::get()?;
+        require!(fee_bps <= 10000, DuelError::InvalidFeeBps);
+        require!(duration_seconds > 0, DuelError::InvalidDurationBounds);
         
-        duel.creator = ctx.accounts.creator.key();
-        duel.opponent = Pubkey::default(); // To be filled when accepted
-        duel.stake_amount = stake_amount;
-        duel.created_at = clock.unix_timestamp;
-        duel.start_time = 0;
-        duel.end_time = 0;
-        duel.duration = duration_seconds;
-        duel.status = DuelStatus::Pending;
-        duel.creator_stake_deposited = false;
-        duel.opponent_stake_deposited = false;
-        duel.allowed_tokens = allowed_tokens;
-        duel.creator_starting_value = 0;
-        duel.opponent_starting_value = 0;
-        duel.creator_final_value = 0;
-        duel.opponent_final_value = 0;
-        duel.winner = DuelWinner::None;
-        
-        // Increment protocol stats
         let protocol = &mut ctx.accounts.protocol;
-        protocol.total_duels += 1;
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.proposal_id = protocol.total_fee_proposals;
+        proposal.proposed_fee_bps = fee_bps;
+        proposal.proposer = ctx.accounts.authority.key();
+        proposal.votes_for = 0;
+        proposal.votes_against = 0;
+        proposal.voting_ends_at = Clock::get()?
+            .unix_timestamp
+            .checked_add(duration_seconds)
+            .ok_or(DuelError::Overflow)?;
+        proposal.executed = false;
+        
+        protocol.total_fee_proposals += 1;
+        
+        emit!(FeeProposalCreated {
+            proposal: proposal.key(),
+            proposal_id: proposal.proposal_id,
+            proposed_fee_bps: fee_bps,
+            voting_ends_at: proposal.voting_ends_at,
+        });
         
         Ok(())
     }
 
-    // Accept a duel challenge
-    pub fn accept_duel(ctx: Context<AcceptDuel>) -> Result<()> {
-        let duel = &mut ctx.accounts.duel;
-        let _clock = Clock::get()?;
-        
-        require!(duel.status == DuelStatus::Pending, DuelError::InvalidStatus);
-        require!(duel.opponent == Pubkey::default(), DuelError::DuelAlreadyAccepted);
-        
-        duel.opponent = ctx.accounts.opponent.key();
-        duel.status = DuelStatus::Accepted;
+    // Vote on a fee proposal. Each player profile votes once; the vote record
+    // PDA cannot be created twice.
+    pub fn vote_on_fee_proposal(
+        ctx: Context<VoteOnFeeProposal>,
+        _proposal_id: u64,
+        approve: bool,
+    ) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(!proposal.executed, DuelError::ProposalAlreadyExecuted);
+        require!(
+            Clock::get()?.unix_timestamp < proposal.voting_ends_at,
+            DuelError::VotingClosed
+        );
         
-        msg!("Duel accepted. Both parties must deposit stakes to begin.");
+        if approve {
+            proposal.votes_for = proposal.votes_for.checked_add(1).ok_or(DuelError::Overflow)?;
+        } else {
+            proposal.votes_against = proposal.votes_against.checked_add(1).ok_or(DuelError::Overflow)?;
+        }
         
+        let vote = &mut ctx.accounts.vote;
+        vote.proposal = proposal.key();
+        vote.voter = ctx.accounts.voter.key();
+        vote.approve = approve;
         Ok(())
     }
 
-    // Deposit stake for the duel
-    pub fn deposit_stake(ctx: Context<DepositStake>) -> Result<()> {
-        let duel = &mut ctx.accounts.duel;
-        let clock = Clock::get()?;
+    // Apply a fee proposal that passed once its voting period is over
+    pub fn execute_fee_proposal(ctx: Context<ExecuteFeeProposal>, _proposal_id: u64) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(!proposal.executed, DuelError::ProposalAlreadyExecuted);
+        require!(
+            Clock::get()?.unix_timestamp >= proposal.voting_ends_at,
+            DuelError::VotingStillOpen
+        );
+        require!(proposal.votes_for > proposal.votes_against, DuelError::ProposalRejected);
         
-        require!(duel.status == DuelStatus::Accepted, DuelError::InvalidStatus);
+        let protocol = &mut ctx.accounts.protocol;
+        let old_fee_bps = protocol.fee_bps;
+        protocol.fee_bps = proposal.proposed_fee_bps;
+        proposal.executed = true;
         
-        // Determine if depositor is creator or opponent
-        let is_creator = ctx.accounts.depositor.key() == duel.creator;
-        let is_opponent = ctx.accounts.depositor.key() == duel.opponent;
+        emit!(FeeProposalExecuted {
+            proposal: proposal.key(),
+            old_fee_bps,
+            new_fee_bps: protocol.fee_bps,
+        });
         
-        require!(is_creator || is_opponent, DuelError::NotParticipant);
+        Ok(())
+    }
+
+    // Permanently retire the protocol, returning the rent of the protocol and
+    // its singletons (heatmap, ranked queue, category leaderboards passed as
+    // remaining accounts) to the authority. Only allowed once no duel is
+    // pending, accepted or funded and the ranked queue is empty.
+    pub fn close_protocol<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CloseProtocol<'info>>,
+    ) -> Result<()> {
+        let protocol = &ctx.accounts.protocol;
+        require!(
+            protocol.total_active_stake == 0
+                && protocol.pending_duels == 0
+                && protocol.accepted_duels == 0,
+            DuelError::ActiveDuelsExist
+        );
         
-        // Transfer stake to escrow
-        anchor_lang::system_program::transfer(
-            CpiContext::new(
-                ctx.accounts.system_program.to_account_info(),
-                anchor_lang::system_program::Transfer {
-                    from: ctx.accounts.depositor.to_account_info(),
-                    to: ctx.accounts.duel_escrow.to_account_info(),
-                },
-            ),
-            duel.stake_amount,
-        )?;
+        let authority = ctx.accounts.authority.to_account_info();
         
-        // Update deposit status
-        if is_creator {
-            duel.creator_stake_deposited = true;
-        } else {
-            duel.opponent_stake_deposited = true;
+        // Queued players have stakes in the queue escrow, so the queue must be empty
+        let ranked_queue = ctx.accounts.ranked_queue.to_account_info();
+        if ranked_queue.owner == &crate::ID {
+            let queue = RankedQueue::try_deserialize(&mut &ranked_queue.try_borrow_data()?[..])?;
+            require!(queue.entries.is_empty(), DuelError::RankedQueueNotEmpty);
+            close_program_account(&ranked_queue, &authority)?;
         }
         
-        // If both have deposited, start the duel
-        if duel.creator_stake_deposited && duel.opponent_stake_deposited {
-            duel.status = DuelStatus::Active;
-            duel.start_time = clock.unix_timestamp;
-            duel.end_time = clock.unix_timestamp + duel.duration;
-            
-            // Record starting portfolio values (would be fetched from oracle)
-            duel.creator_starting_value = duel.stake_amount;
-            duel.opponent_starting_value = duel.stake_amount;
-            
-            msg!("Duel started! Trading period ends at {}", duel.end_time);
+        for leaderboard_info in ctx.remaining_accounts.iter() {
+            let leaderboard: Account<CategoryLeaderboard> = Account::try_from(leaderboard_info)?;
+            let (leaderboard_key, _) = Pubkey::find_program_address(
+                &[b"cat_leaderboard".as_ref(), &[leaderboard.category as u8]],
+                &crate::ID,
+            );
+            require_keys_eq!(
+                leaderboard_info.key(),
+                leaderboard_key,
+                DuelError::InvalidLeaderboardAccount
+            );
+            leaderboard.close(authority.clone())?;
         }
         
+        emit!(ProtocolClosed {
+            authority: protocol.authority,
+            closed_at: Clock::get()?.unix_timestamp,
+            total_volume_lifetime: protocol.total_volume,
+        });
+        
         Ok(())
     }
 
-    // Update trading positions (called by oracle)
-    pub fn update_positions(
-        ctx: Context<UpdatePositions>,
-        creator_value: u64,
-        opponent_value: u64,
+    // Set the loyalty fee discount and the settled-duel count that earns it
+    pub fn update_loyalty_params(
+        ctx: Context<UpdateProtocol>,
+        loyalty_discount_threshold: u32,
+        loyalty_discount_bps: u16,
     ) -> Result<()> {
-        let duel = &mut ctx.accounts.duel;
-        let clock = Clock::get()?;
+        require!(loyalty_discount_bps <= 10000, DuelError::InvalidLoyaltyDiscount);
         
-        require!(duel.status == DuelStatus::Active, DuelError::InvalidStatus);
-        require!(clock.unix_timestamp <= duel.end_time, DuelError::DuelExpired);
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.loyalty_discount_threshold = loyalty_discount_threshold;
+        protocol.loyalty_discount_bps = loyalty_discount_bps;
+        Ok(())
+    }
+
+    // Set the expected oracle update cadence; new duels require one update
+    // per interval of their duration before they can settle
+    pub fn update_min_update_interval(
+        ctx: Context<UpdateProtocol>,
+        min_update_interval_seconds: i64,
+    ) -> Result<()> {
+        require!(min_update_interval_seconds > 0, DuelError::InvalidDurationBounds);
+        ctx.accounts.protocol.min_update_interval_seconds = min_update_interval_seconds;
+        Ok(())
+    }
+
+    // Set how long a manually expiring duel may run before anyone can settle it
+    pub fn update_max_manual_duration(
+        ctx: Context<UpdateProtocol>,
+        max_manual_duration: i64,
+    ) -> Result<()> {
+        require!(max_manual_duration > 0, DuelError::InvalidDurationBounds);
+        ctx.accounts.protocol.max_manual_duration = max_manual_duration;
+        Ok(())
+    }
+
+    // Set how long the oracle has to initialize a funded duel
+    pub fn update_oracle_init_deadline(
+        ctx: Context<UpdateProtocol>,
+        oracle_init_deadline_seconds: i64,
+    ) -> Result<()> {
+        require!(oracle_init_deadline_seconds > 0, DuelError::InvalidOracleInitDeadline);
+        ctx.accounts.protocol.oracle_init_deadline_seconds = oracle_init_deadline_seconds;
+        Ok(())
+    }
+
+    // Set the longest allowed wait between the two deposits (0 disables the check)
+    pub fn update_max_deposit_gap(
+        ctx: Context<UpdateProtocol>,
+        max_deposit_gap_seconds: i64,
+    ) -> Result<()> {
+        require!(max_deposit_gap_seconds >= 0, DuelError::InvalidDepositGap);
+        ctx.accounts.protocol.max_deposit_gap_seconds = max_deposit_gap_seconds;
+        Ok(())
+    }
+
+    // Propose a new treasury. It takes effect once the new treasury confirms
+    // after TREASURY_CHANGE_TIMELOCK_SECONDS, giving stakeholders time to object.
+    pub fn propose_treasury_change(
+        ctx: Context<UpdateProtocol>,
+        new_treasury: Pubkey,
+    ) -> Result<()> {
+        require!(new_treasury != Pubkey::default(), DuelError::InvalidTreasury);
         
-        // In production, verify oracle signature
-        duel.creator_final_value = creator_value;
-        duel.opponent_final_value = opponent_value;
+        let protocol = &mut ctx.accounts.protocol;
+        let now = Clock::get()?.unix_timestamp;
+        protocol.pending_treasury = new_treasury;
+        protocol.treasury_change_proposed_at = now;
         
-        emit!(PositionUpdate {
-            duel: duel.key(),
-            creator_value,
-            opponent_value,
-            timestamp: clock.unix_timestamp,
+        emit!(TreasuryChangePending {
+            old_treasury: protocol.treasury,
+            new_treasury,
+            unlocks_at: now + TREASURY_CHANGE_TIMELOCK_SECONDS,
         });
+        Ok(())
+    }
+
+    // Complete a proposed treasury change, signed by the new treasury
+    pub fn confirm_treasury_change(ctx: Context<ConfirmTreasuryChange>) -> Result<()> {
+        let protocol = &mut ctx.accounts.protocol;
+        require!(
+            protocol.pending_treasury != Pubkey::default(),
+            DuelError::NoPendingTreasuryChange
+        );
+        
+        let unlocks_at = protocol.treasury_change_proposed_at + TREASURY_CHANGE_TIMELOCK_SECONDS;
+        if Clock::get()?.unix_timestamp < unlocks_at {
+            msg!("Treasury change unlocks at {}", unlocks_at);
+            return Err(DuelError::TimeLockNotExpired.into());
+        }
         
+        protocol.treasury = protocol.pending_treasury;
+        protocol.pending_treasury = Pubkey::default();
+        protocol.treasury_change_proposed_at = 0;
         Ok(())
     }
 
-    // Settle the duel and distribute winnings
-    pub fn settle_duel(ctx: Context<SettleDuel>) -> Result<()> {
-        let duel = &mut ctx.accounts.duel;
+    // Withdraw a pending treasury change
+    pub fn cancel_treasury_change(ctx: Context<UpdateProtocol>) -> Result<()> {
         let protocol = &mut ctx.accounts.protocol;
-        let clock = Clock::get()?;
+        require!(
+            protocol.pending_treasury != Pubkey::default(),
+            DuelError::NoPendingTreasuryChange
+        );
         
-        require!(duel.status == DuelStatus::Active, DuelError::InvalidStatus);
-        require!(clock.unix_timestamp >= duel.end_time, DuelError::DuelNotExpired);
+        protocol.pending_treasury = Pubkey::default();
+        protocol.treasury_change_proposed_at = 0;
+        Ok(())
+    }
+
+    // Set the non-refundable fee charged to creators for each new duel
+    pub fn update_creation_fee(
+        ctx: Context<UpdateProtocol>,
+        creation_fee_lamports: u64,
+    ) -> Result<()> {
+        ctx.accounts.protocol.creation_fee_lamports = creation_fee_lamports;
+        Ok(())
+    }
+
+    // Set how far a position value may move between two oracle updates
+    pub fn update_max_update_delta(
+        ctx: Context<UpdateProtocol>,
+        max_update_value_delta_bps: u32,
+    ) -> Result<()> {
+        ctx.accounts.protocol.max_update_value_delta_bps = max_update_value_delta_bps;
+        Ok(())
+    }
+
+    // Publish the SHA-256 of the current whitepaper / protocol spec
+    pub fn update_whitepaper_hash(ctx: Context<UpdateProtocol>, hash: [u8; 32]) -> Result<()> {
+        let protocol = &mut ctx.accounts.protocol;
+        emit!(WhitepaperUpdated {
+            old_hash: protocol.whitepaper_hash,
+            new_hash: hash,
+            updated_by: ctx.accounts.authority.key(),
+        });
+        protocol.whitepaper_hash = hash;
+        Ok(())
+    }
+
+    // Publish the SHA-256 of the current terms of service
+    pub fn update_terms_hash(ctx: Context<UpdateProtocol>, hash: [u8; 32]) -> Result<()> {
+        let protocol = &mut ctx.accounts.protocol;
+        emit!(TermsUpdated {
+            old_hash: protocol.terms_hash,
+            new_hash: hash,
+            updated_by: ctx.accounts.authority.key(),
+        });
+        protocol.terms_hash = hash;
+        Ok(())
+    }
+
+    // Configure the bonus paid to creators whose duels are accepted quickly.
+    // The bonus comes out of the protocol fee: it is charged on one stake while
+    // the fee is charged on both, so it may be at most twice the fee rate, and
+    // settlement caps it at the fee actually charged.
+    pub fn update_quick_accept_bonus(
+        ctx: Context<UpdateProtocol>,
+        bonus_bps: u16,
+        bonus_condition: QuickAcceptBonusCondition,
+    ) -> Result<()> {
+        let protocol = &mut ctx.accounts.protocol;
+        require!(
+            bonus_bps as u32 <= 2 * protocol.fee_bps as u32,
+            DuelError::InvalidBonusBps
+        );
+        
+        protocol.creator_quick_accept_bonus_bps = bonus_bps;
+        protocol.bonus_condition = bonus_condition;
+        Ok(())
+    }
+
+    // Configure ranked matchmaking
+    pub fn update_ranked_queue_params(
+        ctx: Context<UpdateProtocol>,
+        elo_match_range: u32,
+        queue_timeout_seconds: i64,
+    ) -> Result<()> {
+        require!(queue_timeout_seconds >= 0, DuelError::InvalidQueueTimeout);
+
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.elo_match_range = elo_match_range;
+        protocol.queue_timeout_seconds = queue_timeout_seconds;
+        Ok(())
+    }
+
+    // Create the top-players leaderboard for one duel category
+    pub fn create_category_leaderboard(
+        ctx: Context<CreateCategoryLeaderboard>,
+        category: DuelCategory,
+    ) -> Result<()> {
+        let leaderboard = &mut ctx.accounts.category_leaderboard;
+        leaderboard.category = category;
+        leaderboard.entries = Vec::new();
+        Ok(())
+    }
+    
+    // Create the protocol-wide activity heatmap
+    pub fn create_heatmap(ctx: Context<CreateHeatmap>) -> Result<()> {
+        let heatmap = &mut ctx.accounts.heatmap;
+        heatmap.hourly_counts = [0; HEATMAP_BUCKETS];
+        heatmap.hourly_settles = [0; HEATMAP_BUCKETS];
+        Ok(())
+    }
+    
+    // Clear one hour-of-week bucket of the heatmap (authority only)
+    pub fn reset_heatmap_bucket(ctx: Context<ResetHeatmapBucket>, idx: u8) -> Result<()> {
+        let idx = idx as usize;
+        require!(idx < HEATMAP_BUCKETS, DuelError::InvalidHeatmapBucket);
+        
+        let heatmap = &mut ctx.accounts.heatmap;
+        heatmap.hourly_counts[idx] = 0;
+        heatmap.hourly_settles[idx] = 0;
+        Ok(())
+    }
+
+    // Open a round-robin pool. Each participant pays `stake_per_duel` into the
+    // pool prize on joining and separately stakes every pairwise duel.
+    pub fn create_pool(
+        ctx: Context<CreatePool>,
+        max_participants: u8,
+        stake_per_duel: u64,
+        duration: i64,
+    ) -> Result<()> {
+        require!(
+            (2..=MAX_POOL_PARTICIPANTS as u8).contains(&max_participants),
+            DuelError::InvalidPoolSize
+        );
+        let protocol = &mut ctx.accounts.protocol;
+        require!(duration >= protocol.min_duel_duration, DuelError::DurationTooShort);
+        require!(duration <= protocol.max_duel_duration, DuelError::DurationTooLong);
+        require!(
+            stake_per_duel >= Rent::get()?.minimum_balance(0),
+            DuelError::StakeBelowRentExempt
+        );
         
-        // Calculate PnL percentages
-        let creator_pnl = calculate_pnl(duel.creator_starting_value, duel.creator_final_value);
-        let opponent_pnl = calculate_pnl(duel.opponent_starting_value, duel.opponent_final_value);
+        let pool = &mut ctx.accounts.pool;
+        pool.pool_id = protocol.total_pools;
+        pool.creator = ctx.accounts.creator.key();
+        pool.max_participants = max_participants;
+        pool.stake_per_duel = stake_per_duel;
+        pool.duration = duration;
+        pool.participants = Vec::new();
+        pool.duel_matrix = Vec::new();
+        pool.scores = Vec::new();
+        pool.results_recorded = Vec::new();
+        pool.started = false;
+        pool.finalized = false;
+        pool.winner = Pubkey::default();
         
-        // Determine winner
-        let (winner, winner_account) = if creator_pnl > opponent_pnl {
-            (DuelWinner::Creator, ctx.accounts.creator.to_account_info())
-        } else if opponent_pnl > creator_pnl {
-            (DuelWinner::Opponent, ctx.accounts.opponent.to_account_info())
-        } else {
-            (DuelWinner::Draw, ctx.accounts.creator.to_account_info()) // Draw handling
-        };
+        protocol.total_pools += 1;
+        Ok(())
+    }
+
+    // Join a pool that has not started, paying the entry into the pool prize
+    pub fn join_pool(ctx: Context<JoinPool>) -> Result<()> {
+        require!(
+            !ctx.accounts.protocol.is_instruction_paused(IX_CREATE_DUEL),
+            DuelError::InstructionPaused
+        );
+        require!(
+            !ctx.accounts.protocol.require_profile_for_participation
+                || ctx.accounts.player_profile.is_some(),
+            DuelError::ProfileRequired
+        );
         
-        // Calculate payouts
-        let total_stake = duel.stake_amount * 2;
-        let protocol_fee = (total_stake * protocol.fee_bps as u64) / 10000;
-        let winner_payout = total_stake - protocol_fee;
+        let player = ctx.accounts.player.key();
+        let pool = &mut ctx.accounts.pool;
         
-        // Use proper CPI transfers instead of direct lamport manipulation
-        let duel_key = duel.key();
-        let escrow_seeds = &[
-            b"escrow",
-            duel_key.as_ref(),
-            &[ctx.bumps.duel_escrow],
-        ];
-        let signer = &[&escrow_seeds[..]];
+        require!(!pool.started, DuelError::PoolAlreadyStarted);
+        require!(!pool.participants.contains(&player), DuelError::AlreadyInPool);
+        require!(
+            pool.participants.len() < pool.max_participants as usize,
+            DuelError::PoolFull
+        );
         
-        // Transfer protocol fee to treasury
         anchor_lang::system_program::transfer(
-            CpiContext::new_with_signer(
+            CpiContext::new(
                 ctx.accounts.system_program.to_account_info(),
                 anchor_lang::system_program::Transfer {
-                    from: ctx.accounts.duel_escrow.to_account_info(),
-                    to: ctx.accounts.treasury.to_account_info(),
+                    from: ctx.accounts.player.to_account_info(),
+                    to: ctx.accounts.pool_escrow.to_account_info(),
                 },
-                signer,
             ),
-            protocol_fee,
+            pool.stake_per_duel,
         )?;
         
-        // Transfer winnings
-        if winner == DuelWinner::Draw {
-            // Return stakes minus half fee each
-            let refund = duel.stake_amount - (protocol_fee / 2);
-            
-            anchor_lang::system_program::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.system_program.to_account_info(),
-                    anchor_lang::system_program::Transfer {
-                        from: ctx.accounts.duel_escrow.to_account_info(),
-                        to: ctx.accounts.creator.to_account_info(),
-                    },
-                    signer,
-                ),
-                refund,
-            )?;
-            
-            anchor_lang::system_program::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.system_program.to_account_info(),
-                    anchor_lang::system_program::Transfer {
-                        from: ctx.accounts.duel_escrow.to_account_info(),
-                        to: ctx.accounts.opponent.to_account_info(),
-                    },
-                    signer,
-                ),
-                refund,
-            )?;
-        } else {
-            anchor_lang::system_program::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.system_program.to_account_info(),
-                    anchor_lang::system_program::Transfer {
-                        from: ctx.accounts.duel_escrow.to_account_info(),
-                        to: winner_account,
-                    },
-                    signer,
-                ),
-                winner_payout,
-            )?;
-        }
+        pool.participants.push(player);
+        pool.scores.push(0);
+        Ok(())
+    }
+
+    // Leave a pool that has not started, refunding the entry. A pool that
+    // never fills is wound down by its participants leaving.
+    pub fn leave_pool(ctx: Context<LeavePool>) -> Result<()> {
+        let player = ctx.accounts.player.key();
+        let pool_key = ctx.accounts.pool.key();
+        let pool = &mut ctx.accounts.pool;
+        
+        require!(!pool.started, DuelError::PoolAlreadyStarted);
+        let idx = pool
+            .participants
+            .iter()
+            .position(|participant| *participant == player)
+            .ok_or(DuelError::NotInPool)?;
         
-        // Update duel status
-        duel.status = DuelStatus::Settled;
-        duel.winner = winner;
+        let pool_escrow_seeds = &[
+            b"pool_escrow".as_ref(),
+            pool_key.as_ref(),
+            &[ctx.bumps.pool_escrow],
+        ];
+        transfer_from_escrow(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.pool_escrow.to_account_info(),
+            &ctx.accounts.player.to_account_info(),
+            pool.stake_per_duel,
+            &[&pool_escrow_seeds[..]],
+        )?;
         
-        // Update protocol stats
-        protocol.total_volume += total_stake;
+        pool.participants.remove(idx);
+        pool.scores.remove(idx);
+        Ok(())
+    }
+
+    // Start a full pool by creating every pairwise duel. Remaining accounts are
+    // (duel, audit log) pairs, one per pairing (i, j) with i < j in participant
+    // order, where each duel is the PDA [b"pool_duel", pool, i, j]. Large pools
+    // need a raised compute budget.
+    pub fn start_pool<'info>(ctx: Context<'_, '_, 'info, 'info, StartPool<'info>>) -> Result<()> {
+        require!(
+            !ctx.accounts.protocol.is_instruction_paused(IX_CREATE_DUEL),
+            DuelError::InstructionPaused
+        );
         
-        emit!(DuelSettled {
-            duel: duel.key(),
-            winner,
-            creator_pnl,
-            opponent_pnl,
-            winner_payout,
-            protocol_fee,
-        });
+        let pool_key = ctx.accounts.pool.key();
+        let pool = &mut ctx.accounts.pool;
+        let protocol = &mut ctx.accounts.protocol;
+        let clock = Clock::get()?;
+        
+        require!(!pool.started, DuelError::PoolAlreadyStarted);
+        require!(
+            pool.participants.len() == pool.max_participants as usize,
+            DuelError::PoolNotFull
+        );
+        
+        // Remaining accounts are (duel, audit log) pairs, one per pairing
+        let n = pool.participants.len();
+        require!(
+            ctx.remaining_accounts.len() == n * (n - 1),
+            DuelError::InvalidPoolAccounts
+        );
         
+        let mut duel_accounts = ctx.remaining_accounts.iter();
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let duel_info = duel_accounts.next().ok_or(DuelError::InvalidPoolAccounts)?;
+                let audit_info = duel_accounts.next().ok_or(DuelError::InvalidPoolAccounts)?;
+                // Seeded by the pool and pairing, so pools never race for an address
+                let (pairing_i, pairing_j) = ([i as u8], [j as u8]);
+                let (duel_key, bump) = Pubkey::find_program_address(
+                    &[b"pool_duel", pool_key.as_ref(), &pairing_i, &pairing_j],
+                    &crate::ID,
+                );
+                require_keys_eq!(duel_info.key(), duel_key, DuelError::InvalidPoolAccounts);
+                
+                create_duel_account(
+                    &ctx.accounts.system_program.to_account_info(),
+                    &ctx.accounts.creator.to_account_info(),
+                    duel_info,
+                    &[b"pool_duel", pool_key.as_ref(), &pairing_i, &pairing_j, &[bump]],
+                )?;
+                let duel = accepted_duel(
+                    pool.participants[i],
+                    pool.participants[j],
+                    pool.stake_per_duel,
+                    pool.duration,
+                    protocol.default_allowed_tokens.clone(),
+                    clock.unix_timestamp,
+                );
+                let mut data = duel_info.try_borrow_mut_data()?;
+                duel.try_serialize(&mut &mut data[..])?;
+                drop(data);
+                
+                create_audit_log(
+                    &ctx.accounts.system_program.to_account_info(),
+                    &ctx.accounts.creator.to_account_info(),
+                    audit_info,
+                    duel_key,
+                    "start_pool",
+                    audit_args_hash(&())?,
+                )?;
+                
+                pool.duel_matrix.push(duel_key);
+                pool.results_recorded.push(false);
+                track_duel_status(protocol, None, DuelStatus::Accepted);
+                protocol.total_duels = protocol
+                    .total_duels
+                    .checked_add(1)
+                    .ok_or(DuelError::TotalDuelsOverflow)?;
+            }
+        }
+        
+        pool.started = true;
         Ok(())
     }
 
-    // Cancel a pending duel
-    pub fn cancel_duel(ctx: Context<CancelDuel>) -> Result<()> {
-        let duel = &mut ctx.accounts.duel;
+    // Credit the winner of a finished pool duel (callable by anyone). Draws and
+    // cancelled duels are recorded without a score.
+    pub fn record_pool_result(ctx: Context<RecordPoolResult>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let duel = &ctx.accounts.duel;
         
-        require!(duel.status == DuelStatus::Pending, DuelError::CannotCancel);
-        require!(ctx.accounts.creator.key() == duel.creator, DuelError::Unauthorized);
+        let idx = pool
+            .duel_matrix
+            .iter()
+            .position(|key| *key == duel.key())
+            .ok_or(DuelError::DuelNotInPool)?;
+        require!(!pool.results_recorded[idx], DuelError::PoolResultAlreadyRecorded);
+        require!(
+            matches!(duel.status, DuelStatus::Settled | DuelStatus::Cancelled),
+            DuelError::InvalidStatus
+        );
         
-        duel.status = DuelStatus::Cancelled;
+        let winner = match (duel.status, duel.winner) {
+            (DuelStatus::Settled, DuelWinner::Creator) => Some(duel.creator),
+            (DuelStatus::Settled, DuelWinner::Opponent) => Some(duel.opponent),
+            _ => None,
+        };
+        if let Some(winner) = winner {
+            if let Some(participant) = pool.participants.iter().position(|p| *p == winner) {
+                pool.scores[participant] += 1;
+            }
+        }
         
+        pool.results_recorded[idx] = true;
         Ok(())
     }
-}
 
-// Account structures
-#[account]
-pub struct Protocol {
-    pub authority: Pubkey,
+    // Pay the pool prize to the highest scorer once every result is recorded.
+    // Ties go to the participant who joined first.
+    pub fn finalize_pool(ctx: Context<FinalizePool>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        
+        require!(pool.started, DuelError::PoolNotStarted);
+        require!(!pool.finalized, DuelError::PoolAlreadyFinalized);
+        require!(
+            pool.results_recorded.iter().all(|recorded| *recorded),
+            DuelError::PoolResultsPending
+        );
+        
+        let mut winner_idx = 0;
+        for (idx, score) in pool.scores.iter().enumerate() {
+            if *score > pool.scores[winner_idx] {
+                winner_idx = idx;
+            }
+        }
+        let winner = pool.participants[winner_idx];
+        require_keys_eq!(ctx.accounts.winner.key(), winner, DuelError::PoolWinnerMismatch);
+        
+        let prize = pool
+            .stake_per_duel
+            .checked_mul(pool.participants.len() as u64)
+            .ok_or(DuelError::Overflow)?;
+        let pool_key = pool.key();
+        let pool_escrow_seeds = &[
+            b"pool_escrow".as_ref(),
+            pool_key.as_ref(),
+            &[ctx.bumps.pool_escrow],
+        ];
+        transfer_from_escrow(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.pool_escrow.to_account_info(),
+            &ctx.accounts.winner.to_account_info(),
+            prize,
+            &[&pool_escrow_seeds[..]],
+        )?;
+        
+        pool.finalized = true;
+        pool.winner = winner;
+        
+        emit!(PoolFinalized {
+            pool: pool_key,
+            winner,
+            score: pool.scores[winner_idx],
+            prize,
+        });
+        
+        Ok(())
+    }
+
+    // Emit a snapshot of protocol-wide metrics for monitoring (callable by anyone)
+    pub fn protocol_health_check(ctx: Context<ProtocolHealthCheck>) -> Result<()> {
+        let protocol = &ctx.accounts.protocol;
+        emit!(ProtocolHealth {
+            treasury_balance: ctx.accounts.treasury.lamports(),
+            total_duels: protocol.total_duels,
+            total_volume: protocol.total_volume,
+            total_active_stake: protocol.total_active_stake,
+            pending_duels: protocol.pending_duels,
+            accepted_duels: protocol.accepted_duels,
+            paused_instructions: protocol.paused_instructions,
+            version: PROGRAM_VERSION,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Estimate the compute units settle_duel will need for this duel, so
+    // clients can size their ComputeBudget request. Also returned as return data.
+    pub fn estimate_settle_compute(
+        ctx: Context<EstimateSettleCompute>,
+        is_draw: bool,
+        with_category_leaderboard: bool,
+    ) -> Result<u64> {
+        let estimated_cus =
+            settle_compute_estimate(is_draw, ctx.accounts.duel.use_wsol, with_category_leaderboard);
+        
+        emit!(ComputeEstimate {
+            duel: ctx.accounts.duel.key(),
+            estimated_cus,
+            is_draw,
+        });
+        Ok(estimated_cus)
+    }
+
+    // Emit the duel's end time and seconds left in its trading period (callable by anyone)
+    pub fn get_time_remaining(ctx: Context<GetTimeRemaining>) -> Result<()> {
+        let duel = &ctx.accounts.duel;
+        let clock = Clock::get()?;
+        let end_time = estimated_end_time(duel);
+        let remaining_seconds = match end_time {
+            Some(end_time) if !is_expired(duel, &clock) => end_time - clock.unix_timestamp,
+            _ => 0,
+        };
+        
+        emit!(TimeRemaining {
+            duel: duel.key(),
+            end_time,
+            remaining_seconds,
+            status: duel.status,
+        });
+        Ok(())
+    }
+
+    // Create the singleton ranked matchmaking queue
+    pub fn create_ranked_queue(ctx: Context<CreateRankedQueue>) -> Result<()> {
+        ctx.accounts.ranked_queue.entries = Vec::new();
+        Ok(())
+    }
+
+    // Join the ranked queue. If a waiting player with the same stake is within
+    // the ELO match range, a funded duel is created between the two, seeded by
+    // the caller and `nonce` like create_duel; otherwise the caller waits in
+    // the queue.
+    pub fn join_ranked_queue(
+        ctx: Context<JoinRankedQueue>,
+        stake_amount: u64,
+        nonce: u64,
+    ) -> Result<()> {
+        let compute_start = log_compute!();
+        
+        require!(
+            !ctx.accounts.protocol.is_instruction_paused(IX_CREATE_DUEL),
+            DuelError::InstructionPaused
+        );
+        
+        let player = ctx.accounts.player.key();
+        let elo = ctx.accounts.player_profile.elo_rating;
+        let loyal = is_loyal(
+            Some(&ctx.accounts.player_profile),
+            ctx.accounts.protocol.loyalty_discount_threshold,
+        );
+        let clock = Clock::get()?;
+        
+        let rent = Rent::get()?;
+        require!(
+            stake_amount >= rent.minimum_balance(0),
+            DuelError::StakeBelowRentExempt
+        );
+        
+        let queue = &mut ctx.accounts.ranked_queue;
+        require!(
+            !queue.entries.iter().any(|entry| entry.wallet == player),
+            DuelError::AlreadyInQueue
+        );
+        
+        // Hold the stake in the queue escrow until matched or refunded
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.player.to_account_info(),
+                    to: ctx.accounts.queue_escrow.to_account_info(),
+                },
+            ),
+            stake_amount,
+        )?;
+        
+        let match_range = ctx.accounts.protocol.elo_match_range;
+        let matched = queue.entries.iter().position(|entry| {
+            entry.stake_amount == stake_amount && entry.elo.abs_diff(elo) <= match_range
+        });
+        
+        let Some(matched_idx) = matched else {
+            require!(
+                queue.entries.len() < MAX_RANKED_QUEUE_ENTRIES,
+                DuelError::QueueFull
+            );
+            queue.entries.push(RankedQueueEntry {
+                wallet: player,
+                elo,
+                stake_amount,
+                joined_at: clock.unix_timestamp,
+                loyal,
+            });
+            return Ok(());
+        };
+        let waiting = queue.entries.remove(matched_idx);
+        
+        let protocol = &mut ctx.accounts.protocol;
+        let nonce_bytes = nonce.to_le_bytes();
+        create_duel_account(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.player.to_account_info(),
+            &ctx.accounts.duel.to_account_info(),
+            &[b"duel", player.as_ref(), &nonce_bytes, &[ctx.bumps.duel]],
+        )?;
+        
+        let mut duel = accepted_duel(
+            waiting.wallet,
+            player,
+            stake_amount,
+            RANKED_DUEL_DURATION,
+            protocol.default_allowed_tokens.clone(),
+            clock.unix_timestamp,
+        );
+        duel.creator_stake_deposited = true;
+        duel.opponent_stake_deposited = true;
+        duel.creator_deposit_at = waiting.joined_at;
+        duel.opponent_deposit_at = clock.unix_timestamp;
+        duel.opponent_alias = ctx.accounts.player_profile.display_name;
+        duel.creator_loyal = waiting.loyal;
+        duel.opponent_loyal = loyal;
+        duel.nonce = nonce;
+        activate_ranked_duel(&mut duel, protocol, clock.unix_timestamp)?;
+        
+        let mut data = ctx.accounts.duel.try_borrow_mut_data()?;
+        duel.try_serialize(&mut &mut data[..])?;
+        drop(data);
+        
+        create_audit_log(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.player.to_account_info(),
+            &ctx.accounts.audit_log.to_account_info(),
+            ctx.accounts.duel.key(),
+            "join_ranked_queue",
+            audit_args_hash(&stake_amount)?,
+        )?;
+        
+        // Move both stakes from the queue escrow into the duel escrow
+        let queue_escrow_seeds = &[b"queue_escrow".as_ref(), &[ctx.bumps.queue_escrow]];
+        anchor_lang::system_program::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.queue_escrow.to_account_info(),
+                    to: ctx.accounts.duel_escrow.to_account_info(),
+                },
+                &[&queue_escrow_seeds[..]],
+            ),
+            stake_amount.checked_mul(2).ok_or(DuelError::Overflow)?,
+        )?;
+        
+        protocol.total_duels = protocol
+            .total_duels
+            .checked_add(1)
+            .ok_or(DuelError::TotalDuelsOverflow)?;
+        
+        emit!(MatchFound {
+            player1: waiting.wallet,
+            player2: player,
+            duel: ctx.accounts.duel.key(),
+        });
+        
+        log_compute!("join_ranked_queue", compute_start);
+        
+        Ok(())
+    }
+
+    // Leave the ranked queue and reclaim the stake once the queue timeout has passed
+    pub fn leave_ranked_queue(ctx: Context<LeaveRankedQueue>) -> Result<()> {
+        let player = ctx.accounts.player.key();
+        let clock = Clock::get()?;
+        
+        let queue = &mut ctx.accounts.ranked_queue;
+        let idx = queue
+            .entries
+            .iter()
+            .position(|entry| entry.wallet == player)
+            .ok_or(DuelError::NotInQueue)?;
+        require!(
+            clock.unix_timestamp
+                >= queue.entries[idx].joined_at + ctx.accounts.protocol.queue_timeout_seconds,
+            DuelError::QueueTimeoutNotReached
+        );
+        let entry = queue.entries.remove(idx);
+        
+        let queue_escrow_seeds = &[b"queue_escrow".as_ref(), &[ctx.bumps.queue_escrow]];
+        transfer_from_escrow(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.queue_escrow.to_account_info(),
+            &ctx.accounts.player.to_account_info(),
+            entry.stake_amount,
+            &[&queue_escrow_seeds[..]],
+        )
+    }
+
+    // Configure the ELO-proportional minimum stake for ranked duels
+    pub fn update_ranked_mode(
+        ctx: Context<UpdateProtocol>,
+        ranked_mode_enabled: bool,
+        ranked_min_stake_factor: u64,
+    ) -> Result<()> {
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.ranked_mode_enabled = ranked_mode_enabled;
+        protocol.ranked_min_stake_factor = ranked_min_stake_factor;
+        Ok(())
+    }
+
+    // Set the tokens used for duels created without an explicit token list
+    pub fn set_default_allowed_tokens(
+        ctx: Context<UpdateProtocol>,
+        default_allowed_tokens: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            default_allowed_tokens.len() <= MAX_DEFAULT_ALLOWED_TOKENS,
+            DuelError::TooManyDefaultTokens
+        );
+        // Ranked and pool duels copy the defaults as-is, so normalize them here
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.default_allowed_tokens = normalize_allowed_tokens(default_allowed_tokens);
+        Ok(())
+    }
+
+    // Raise (or lower) the minimum client version allowed to create duels
+    pub fn update_min_client_version(
+        ctx: Context<UpdateProtocol>,
+        min_client_version: u32,
+    ) -> Result<()> {
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.min_client_version = min_client_version;
+        Ok(())
+    }
+
+    // Enable or disable duel creation for a category
+    pub fn set_category_enabled(
+        ctx: Context<UpdateProtocol>,
+        category: DuelCategory,
+        enabled: bool,
+    ) -> Result<()> {
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.category_enabled[category as usize] = enabled;
+        Ok(())
+    }
+
+    // Update the waiting period between full funding and the start of trading
+    pub fn update_stake_lock_period(
+        ctx: Context<UpdateProtocol>,
+        stake_lock_period_seconds: i64,
+    ) -> Result<()> {
+        require!(stake_lock_period_seconds >= 0, DuelError::InvalidLockPeriod);
+
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.stake_lock_period_seconds = stake_lock_period_seconds;
+        Ok(())
+    }
+
+    // Pause a single instruction (see IX_* constants)
+    pub fn pause_instruction(ctx: Context<UpdateProtocol>, instruction_idx: u8) -> Result<()> {
+        require!(instruction_idx < 16, DuelError::InvalidInstructionIndex);
+
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.paused_instructions |= 1 << instruction_idx;
+        Ok(())
+    }
+
+    // Resume a previously paused instruction
+    pub fn resume_instruction(ctx: Context<UpdateProtocol>, instruction_idx: u8) -> Result<()> {
+        require!(instruction_idx < 16, DuelError::InvalidInstructionIndex);
+
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.paused_instructions &= !(1 << instruction_idx);
+        Ok(())
+    }
+
+    // Update the allowed duel duration range
+    pub fn update_duration_bounds(
+        ctx: Context<UpdateProtocol>,
+        min_duel_duration: i64,
+        max_duel_duration: i64,
+    ) -> Result<()> {
+        require!(
+            min_duel_duration >= 0 && min_duel_duration <= max_duel_duration,
+            DuelError::InvalidDurationBounds
+        );
+
+        let protocol = &mut ctx.accounts.protocol;
+        protocol.min_duel_duration = min_duel_duration;
+        protocol.max_duel_duration = max_duel_duration;
+        Ok(())
+    }
+
+    // Create a player profile
+    pub fn create_player_profile(
+        ctx: Context<CreatePlayerProfile>,
+        display_name: Vec<u8>,
+    ) -> Result<()> {
+        let profile = &mut ctx.accounts.profile;
+        profile.wallet = ctx.accounts.wallet.key();
+        profile.display_name = to_display_name(&display_name)?;
+        profile.created_at = Clock::get()?.unix_timestamp;
+        profile.elo_rating = DEFAULT_ELO_RATING;
+        profile.accepted_terms_hash = [0; 32];
+        profile.duels_participated = 0;
+        profile.win_streak = 0;
+        profile.total_volume = 0;
+        profile.reputation_score = 0;
+        Ok(())
+    }
+
+    // Record that the player accepted the currently published terms
+    pub fn accept_terms(ctx: Context<AcceptTerms>, terms_hash: [u8; 32]) -> Result<()> {
+        require!(
+            terms_hash == ctx.accounts.protocol.terms_hash,
+            DuelError::TermsHashMismatch
+        );
+        
+        ctx.accounts.profile.accepted_terms_hash = terms_hash;
+        Ok(())
+    }
+
+    // Recompute a player's reputation with the current weights and emit its
+    // components (callable by anyone)
+    pub fn compute_reputation_score(ctx: Context<ComputeReputationScore>, player: Pubkey) -> Result<()> {
+        let profile = &mut ctx.accounts.profile;
+        profile.reputation_score = reputation_score(profile, &ctx.accounts.protocol.reputation_weights);
+        
+        emit!(ReputationScore {
+            player,
+            score: profile.reputation_score,
+            elo: profile.elo_rating,
+            win_streak: profile.win_streak,
+            total_volume: profile.total_volume,
+        });
+        Ok(())
+    }
+
+    // Create the caller's (initially empty) friend list
+    pub fn create_friend_list(ctx: Context<CreateFriendList>) -> Result<()> {
+        let friend_list = &mut ctx.accounts.friend_list;
+        friend_list.wallet = ctx.accounts.wallet.key();
+        friend_list.friends = Vec::new();
+        friend_list.pending_requests = Vec::new();
+        Ok(())
+    }
+
+    // Queue a friend request on the target's friend list
+    pub fn send_friend_request(ctx: Context<SendFriendRequest>, target: Pubkey) -> Result<()> {
+        let sender = ctx.accounts.sender.key();
+        require!(sender != target, DuelError::CannotFriendSelf);
+        
+        let target_list = &mut ctx.accounts.target_friend_list;
+        require!(!target_list.friends.contains(&sender), DuelError::AlreadyFriends);
+        require!(
+            !target_list.pending_requests.contains(&sender),
+            DuelError::FriendRequestAlreadySent
+        );
+        require!(
+            target_list.pending_requests.len() < MAX_PENDING_FRIEND_REQUESTS,
+            DuelError::FriendListFull
+        );
+        
+        target_list.pending_requests.push(sender);
+        Ok(())
+    }
+
+    // Accept a pending request, adding each player to the other's friends
+    pub fn accept_friend_request(ctx: Context<UpdateFriendship>, requester: Pubkey) -> Result<()> {
+        let friend_list = &mut ctx.accounts.friend_list;
+        let idx = friend_list
+            .pending_requests
+            .iter()
+            .position(|pending| *pending == requester)
+            .ok_or(DuelError::FriendRequestNotFound)?;
+        
+        let other_list = &mut ctx.accounts.other_friend_list;
+        require!(
+            friend_list.friends.len() < MAX_FRIENDS && other_list.friends.len() < MAX_FRIENDS,
+            DuelError::FriendListFull
+        );
+        
+        friend_list.pending_requests.remove(idx);
+        friend_list.friends.push(requester);
+        other_list.friends.push(friend_list.wallet);
+        // A crossed request from us to the requester is now redundant
+        other_list.pending_requests.retain(|pending| *pending != friend_list.wallet);
+        
+        emit!(FriendAdded {
+            wallet: friend_list.wallet,
+            friend: requester,
+        });
+        
+        Ok(())
+    }
+
+    // Drop a pending request without notifying the requester
+    pub fn decline_friend_request(
+        ctx: Context<DeclineFriendRequest>,
+        requester: Pubkey,
+    ) -> Result<()> {
+        let friend_list = &mut ctx.accounts.friend_list;
+        let idx = friend_list
+            .pending_requests
+            .iter()
+            .position(|pending| *pending == requester)
+            .ok_or(DuelError::FriendRequestNotFound)?;
+        friend_list.pending_requests.remove(idx);
+        Ok(())
+    }
+
+    // Remove a friend from both players' lists
+    pub fn remove_friend(ctx: Context<UpdateFriendship>, friend: Pubkey) -> Result<()> {
+        let friend_list = &mut ctx.accounts.friend_list;
+        let idx = friend_list
+            .friends
+            .iter()
+            .position(|existing| *existing == friend)
+            .ok_or(DuelError::NotFriends)?;
+        friend_list.friends.remove(idx);
+        
+        let wallet = friend_list.wallet;
+        ctx.accounts
+            .other_friend_list
+            .friends
+            .retain(|existing| *existing != wallet);
+        Ok(())
+    }
+
+    // Change the display name shown on duels created or accepted from now on
+    pub fn update_profile_alias(
+        ctx: Context<UpdatePlayerProfile>,
+        display_name: Vec<u8>,
+    ) -> Result<()> {
+        let profile = &mut ctx.accounts.profile;
+        profile.display_name = to_display_name(&display_name)?;
+        Ok(())
+    }
+
+    // Create a new duel challenge
+    pub fn create_duel(
+        ctx: Context<CreateDuel>,
+        nonce: u64,
+        stake_amount: u64,
+        duration_seconds: i64,
+        allowed_tokens: Vec<Pubkey>,
+        args: CreateDuelArgs,
+    ) -> Result<()> {
+        let args_hash = audit_args_hash(&(nonce, stake_amount, duration_seconds, &allowed_tokens, &args))?;
+        let CreateDuelArgs {
+            category,
+            client_version,
+            creator_token_allocation,
+            win_condition,
+            use_wsol,
+            expiry_mode,
+            duel_uses_sol_natively,
+        } = args;
+        init_duel(
+            ctx.accounts,
+            nonce,
+            client_version,
+            NewDuelConfig {
+                stake_amount,
+                duration_seconds,
+                allowed_tokens,
+                category,
+                creator_token_allocation,
+                win_condition,
+                use_wsol,
+                expiry_mode,
+                duel_uses_sol_natively,
+            },
+        )?;
+        
+        let creator = ctx.accounts.creator.key();
+        record_audit(&mut ctx.accounts.audit_log, "create_duel", creator, args_hash)
+    }
+
+    // Save a reusable duel configuration
+    pub fn create_template(
+        ctx: Context<CreateTemplate>,
+        template_id: u64,
+        name: [u8; 32],
+        stake_amount: u64,
+        duration_seconds: i64,
+        allowed_tokens: Vec<Pubkey>,
+        category: DuelCategory,
+    ) -> Result<()> {
+        require!(
+            allowed_tokens.len() <= MAX_TEMPLATE_ALLOWED_TOKENS,
+            DuelError::TooManyAllowedTokens
+        );
+        
+        let template = &mut ctx.accounts.template;
+        template.creator = ctx.accounts.creator.key();
+        template.template_id = template_id;
+        template.name = name;
+        template.stake_amount = stake_amount;
+        template.duration_seconds = duration_seconds;
+        template.allowed_tokens = allowed_tokens;
+        template.category = category;
+        template.use_count = 0;
+        Ok(())
+    }
+
+    // Create a duel from one of the creator's templates. Settings the template
+    // does not store use the create_duel defaults.
+    pub fn create_duel_from_template(
+        ctx: Context<CreateDuelFromTemplate>,
+        nonce: u64,
+        client_version: u32,
+    ) -> Result<()> {
+        let template = &mut ctx.accounts.template;
+        init_duel(
+            &mut ctx.accounts.create_duel,
+            nonce,
+            client_version,
+            NewDuelConfig {
+                stake_amount: template.stake_amount,
+                duration_seconds: template.duration_seconds,
+                allowed_tokens: template.allowed_tokens.clone(),
+                category: template.category,
+                creator_token_allocation: Vec::new(),
+                win_condition: WinCondition::HighestPnl,
+                use_wsol: false,
+                expiry_mode: ExpiryMode::TimeBased,
+                duel_uses_sol_natively: false,
+            },
+        )?;
+        template.use_count += 1;
+        
+        let create_duel = &mut ctx.accounts.create_duel;
+        record_audit(
+            &mut create_duel.audit_log,
+            "create_duel_from_template",
+            create_duel.creator.key(),
+            audit_args_hash(&(nonce, client_version))?,
+        )?;
+        
+        emit!(DuelCreatedFromTemplate {
+            template: template.key(),
+            duel: ctx.accounts.create_duel.duel.key(),
+        });
+        
+        Ok(())
+    }
+
+    // Delete a template and reclaim its rent (creator only)
+    pub fn delete_template(_ctx: Context<DeleteTemplate>) -> Result<()> {
+        Ok(())
+    }
+
+    // Create the WSOL token escrow for a duel created with `use_wsol`. Its
+    // authority is the duel's SOL escrow PDA, and its rent is returned to the
+    // creator when the duel pays out or is cancelled.
+    pub fn create_wsol_escrow(ctx: Context<CreateWsolEscrow>) -> Result<()> {
+        require!(ctx.accounts.duel.use_wsol, DuelError::WsolNotEnabled);
+        require!(
+            matches!(ctx.accounts.duel.status, DuelStatus::Pending | DuelStatus::Accepted),
+            DuelError::InvalidStatus
+        );
+        Ok(())
+    }
+
+    // Accept a duel challenge
+    pub fn accept_duel(ctx: Context<AcceptDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "accept_duel",
+            ctx.accounts.opponent.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        require!(
+            !ctx.accounts.protocol.is_instruction_paused(IX_ACCEPT_DUEL),
+            DuelError::InstructionPaused
+        );
+        require!(
+            !ctx.accounts.protocol.require_profile_for_participation
+                || ctx.accounts.opponent_profile.is_some(),
+            DuelError::ProfileRequired
+        );
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.status == DuelStatus::Pending, DuelError::InvalidStatus);
+        require!(duel.opponent == Pubkey::default(), DuelError::DuelAlreadyAccepted);
+        
+        // Ranked duels need a stake proportional to the higher-rated player's ELO;
+        // both profiles are required so the check cannot be skipped
+        let protocol = &mut ctx.accounts.protocol;
+        if protocol.ranked_mode_enabled {
+            let (Some(creator_profile), Some(opponent_profile)) =
+                (&ctx.accounts.creator_profile, &ctx.accounts.opponent_profile)
+            else {
+                return err!(DuelError::ProfileRequired);
+            };
+            let higher_elo = creator_profile.elo_rating.max(opponent_profile.elo_rating);
+            let required_min_stake = (higher_elo as u64)
+                .checked_mul(protocol.ranked_min_stake_factor)
+                .ok_or(DuelError::Overflow)?
+                / 1000;
+            require!(
+                duel.stake_amount >= required_min_stake,
+                DuelError::StakeTooLowForRank
+            );
+        }
+        
+        duel.opponent = ctx.accounts.opponent.key();
+        duel.status = DuelStatus::Accepted;
+        track_duel_status(protocol, Some(DuelStatus::Pending), DuelStatus::Accepted);
+        if let Some(profile) = &ctx.accounts.opponent_profile {
+            duel.opponent_alias = profile.display_name;
+        }
+        duel.opponent_loyal = is_loyal(
+            ctx.accounts.opponent_profile.as_deref(),
+            protocol.loyalty_discount_threshold,
+        );
+        
+        // Reward creators whose offers attract an opponent quickly
+        if clock.unix_timestamp - duel.created_at <= QUICK_ACCEPT_WINDOW_SECONDS {
+            duel.creator_quick_accept_bonus = duel
+                .stake_amount
+                .checked_mul(protocol.creator_quick_accept_bonus_bps as u64)
+                .ok_or(DuelError::Overflow)?
+                / 10000;
+        }
+        
+        emit!(DuelAccepted {
+            duel: duel.key(),
+            opponent: duel.opponent,
+            opponent_alias: duel.opponent_alias,
+            accepted_at: clock.unix_timestamp,
+        });
+        
+        msg!("Duel accepted. Both parties must deposit stakes to begin.");
+        
+        Ok(())
+    }
+
+    // Deposit stake for the duel
+    pub fn deposit_stake(ctx: Context<DepositStake>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "deposit_stake",
+            ctx.accounts.depositor.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        require!(
+            !ctx.accounts.protocol.is_instruction_paused(IX_DEPOSIT_STAKE),
+            DuelError::InstructionPaused
+        );
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.creator != Pubkey::default(), DuelError::InvalidDuelState);
+        require!(duel.opponent != Pubkey::default(), DuelError::OpponentNotSet);
+        require!(duel.status == DuelStatus::Accepted, DuelError::InvalidStatus);
+        
+        // Determine if depositor is creator or opponent
+        let is_creator = ctx.accounts.depositor.key() == duel.creator;
+        let is_opponent = ctx.accounts.depositor.key() == duel.opponent;
+        
+        require!(is_creator || is_opponent, DuelError::NotParticipant);
+        
+        // Hold the re-entrancy guard across the transfer CPIs below
+        require!(!duel.settling, DuelError::ReentrancyDetected);
+        duel.settling = true;
+        
+        // Defence in depth on top of the seeds constraint: the escrow must be
+        // this duel's PDA and still a plain system account
+        let expected_escrow = Pubkey::create_program_address(
+            &[b"escrow", duel.key().as_ref(), &[ctx.bumps.duel_escrow]],
+            &crate::ID,
+        )
+        .map_err(|_| error!(DuelError::EscrowNotPDA))?;
+        require_keys_eq!(
+            ctx.accounts.duel_escrow.key(),
+            expected_escrow,
+            DuelError::EscrowNotPDA
+        );
+        require_keys_eq!(
+            *ctx.accounts.duel_escrow.owner,
+            anchor_lang::system_program::ID,
+            DuelError::InvalidEscrowOwner
+        );
+        
+        // Likewise re-check the programs every CPI below is sent to
+        require_keys_eq!(
+            ctx.accounts.system_program.key(),
+            anchor_lang::system_program::ID,
+            DuelError::InvalidSystemProgram
+        );
+        if let Some(token_program) = &ctx.accounts.token_program {
+            require_keys_eq!(token_program.key(), token::ID, DuelError::InvalidTokenProgram);
+        }
+        
+        // A second deposit arriving too long after the first cancels the duel:
+        // the late stake is not taken and the first one is refunded
+        let (other_deposited, other_deposit_at) = if is_creator {
+            (duel.opponent_stake_deposited, duel.opponent_deposit_at)
+        } else {
+            (duel.creator_stake_deposited, duel.creator_deposit_at)
+        };
+        let max_gap = ctx.accounts.protocol.max_deposit_gap_seconds;
+        if other_deposited && max_gap > 0 && clock.unix_timestamp - other_deposit_at > max_gap {
+            let counterparty = ctx
+                .accounts
+                .counterparty
+                .as_ref()
+                .ok_or(DuelError::CounterpartyAccountRequired)?
+                .to_account_info();
+            let depositor = ctx.accounts.depositor.to_account_info();
+            let (creator, opponent) = if is_creator {
+                (depositor, counterparty)
+            } else {
+                (counterparty, depositor)
+            };
+            require_keys_eq!(creator.key(), duel.creator, DuelError::NotParticipant);
+            require_keys_eq!(opponent.key(), duel.opponent, DuelError::NotParticipant);
+            
+            let duel_key = duel.key();
+            let escrow_seeds = &[b"escrow".as_ref(), duel_key.as_ref(), &[ctx.bumps.duel_escrow]];
+            unwrap_wsol_escrow(
+                duel,
+                ctx.accounts.wsol_escrow.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+                &ctx.accounts.duel_escrow.to_account_info(),
+                &creator,
+                &ctx.accounts.system_program.to_account_info(),
+                &[&escrow_seeds[..]],
+            )?;
+            refund_deposits(
+                duel,
+                duel_key,
+                ctx.bumps.duel_escrow,
+                &ctx.accounts.duel_escrow.to_account_info(),
+                &creator,
+                Some(&opponent),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+            duel.status = DuelStatus::Cancelled;
+            duel.settling = false;
+            track_duel_status(&mut ctx.accounts.protocol, Some(DuelStatus::Accepted), DuelStatus::Cancelled);
+            
+            emit!(DuelAutoCancelled {
+                duel: duel_key,
+                deposit_gap: clock.unix_timestamp - other_deposit_at,
+            });
+            return Ok(());
+        }
+        
+        // Transfer stake to escrow; WSOL duels hold it in a token account
+        let escrow = if duel.use_wsol {
+            ctx.accounts
+                .wsol_escrow
+                .as_ref()
+                .ok_or(DuelError::WsolEscrowRequired)?
+                .to_account_info()
+        } else {
+            ctx.accounts.duel_escrow.to_account_info()
+        };
+        #[cfg(feature = "strict_balance_checks")]
+        let expected_escrow_balance = escrow.lamports() + duel.stake_amount;
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.depositor.to_account_info(),
+                    to: escrow.clone(),
+                },
+            ),
+            duel.stake_amount,
+        )?;
+        #[cfg(feature = "strict_balance_checks")]
+        assert_balance(&escrow, expected_escrow_balance)?;
+        
+        if duel.use_wsol {
+            let token_program = ctx
+                .accounts
+                .token_program
+                .as_ref()
+                .ok_or(DuelError::WsolEscrowRequired)?;
+            token::sync_native(CpiContext::new(
+                token_program.to_account_info(),
+                token::SyncNative { account: escrow },
+            ))
+            .map_err(|_| error!(DuelError::WrapSolFailed))?;
+        }
+        
+        // Update deposit status
+        if is_creator {
+            duel.creator_stake_deposited = true;
+            duel.creator_deposit_at = clock.unix_timestamp;
+        } else {
+            duel.opponent_stake_deposited = true;
+            duel.opponent_deposit_at = clock.unix_timestamp;
+        }
+        
+        // If both have deposited, hand the duel to the oracle (or lock it for the
+        // waiting period) and create its countdown timer
+        if duel.creator_stake_deposited && duel.opponent_stake_deposited {
+            activate_funded_duel(duel, &mut ctx.accounts.protocol, clock.unix_timestamp)?;
+            
+            let duel_timer = ctx
+                .accounts
+                .duel_timer
+                .as_ref()
+                .ok_or(DuelError::DuelTimerRequired)?
+                .to_account_info();
+            let timer_bump = ctx.bumps.duel_timer.ok_or(DuelError::DuelTimerRequired)?;
+            create_duel_timer(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.depositor.to_account_info(),
+                &duel_timer,
+                duel,
+                timer_bump,
+            )?;
+        }
+        
+        duel.settling = false;
+        Ok(())
+    }
+
+    // Start a locked duel once its lock period has elapsed (callable by anyone)
+    pub fn unlock_duel(ctx: Context<UnlockDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "unlock_duel",
+            ctx.accounts.caller.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.status == DuelStatus::Locked, DuelError::InvalidStatus);
+        require!(clock.unix_timestamp >= duel.stake_locked_until, DuelError::DuelLocked);
+        
+        await_oracle_init(duel, &ctx.accounts.protocol, clock.unix_timestamp);
+        
+        Ok(())
+    }
+
+    // Record an authorized oracle's starting portfolio values and start trading
+    pub fn initialize_oracle_tracking(
+        ctx: Context<InitializeOracleTracking>,
+        creator_starting_value: u64,
+        opponent_starting_value: u64,
+    ) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "initialize_oracle_tracking",
+            ctx.accounts.oracle.key(),
+            audit_args_hash(&(creator_starting_value, opponent_starting_value))?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.status == DuelStatus::PendingOracle, DuelError::InvalidStatus);
+        require!(
+            clock.unix_timestamp <= duel.oracle_init_deadline,
+            DuelError::OracleInitDeadlinePassed
+        );
+        require!(
+            creator_starting_value > 0 && opponent_starting_value > 0,
+            DuelError::InvalidStartingValue
+        );
+        
+        start_duel(duel, clock.unix_timestamp, creator_starting_value, opponent_starting_value);
+        
+        if let Some(duel_timer) = &mut ctx.accounts.duel_timer {
+            duel_timer.start_time = duel.start_time;
+            duel_timer.end_time = duel.end_time;
+        }
+        
+        Ok(())
+    }
+
+    // Cancel a duel the oracle never initialized and refund both stakes
+    // (callable by anyone once the deadline has passed)
+    pub fn expire_oracle_init(ctx: Context<ExpireOracleInit>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "expire_oracle_init",
+            ctx.accounts.caller.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.status == DuelStatus::PendingOracle, DuelError::InvalidStatus);
+        require!(
+            clock.unix_timestamp > duel.oracle_init_deadline,
+            DuelError::OracleInitDeadlineNotReached
+        );
+        
+        let duel_key = duel.key();
+        let escrow_seeds = &[b"escrow".as_ref(), duel_key.as_ref(), &[ctx.bumps.duel_escrow]];
+        unwrap_wsol_escrow(
+            duel,
+            ctx.accounts.wsol_escrow.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.duel_escrow.to_account_info(),
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            &[&escrow_seeds[..]],
+        )?;
+        
+        release_active_stake(&mut ctx.accounts.protocol, duel);
+        
+        let opponent = ctx.accounts.opponent.to_account_info();
+        refund_deposits(
+            duel,
+            duel_key,
+            ctx.bumps.duel_escrow,
+            &ctx.accounts.duel_escrow.to_account_info(),
+            &ctx.accounts.creator.to_account_info(),
+            Some(&opponent),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        duel.status = DuelStatus::Cancelled;
+        
+        msg!("Oracle missed its initialization deadline; duel cancelled");
+        
+        Ok(())
+    }
+
+    // Request a mutual pause; the duel pauses once both participants agree
+    pub fn request_pause(ctx: Context<PauseDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "request_pause",
+            ctx.accounts.participant.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.status == DuelStatus::Active, DuelError::InvalidStatus);
+        check_trading_open(duel, clock.unix_timestamp)?;
+        
+        let participant = ctx.accounts.participant.key();
+        if participant == duel.creator {
+            duel.pause_request_creator = true;
+        } else if participant == duel.opponent {
+            duel.pause_request_opponent = true;
+        } else {
+            return Err(DuelError::NotParticipant.into());
+        }
+        
+        if duel.pause_request_creator && duel.pause_request_opponent {
+            duel.status = DuelStatus::Paused;
+            duel.pause_start = clock.unix_timestamp;
+            duel.pause_request_creator = false;
+            duel.pause_request_opponent = false;
+            
+            msg!("Duel paused at {}", duel.pause_start);
+        }
+        
+        Ok(())
+    }
+
+    // Request to resume a paused duel; the end time is extended by the pause
+    // duration once both participants agree
+    pub fn resume_duel(ctx: Context<PauseDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "resume_duel",
+            ctx.accounts.participant.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.status == DuelStatus::Paused, DuelError::InvalidStatus);
+        
+        let participant = ctx.accounts.participant.key();
+        if participant == duel.creator {
+            duel.resume_request_creator = true;
+        } else if participant == duel.opponent {
+            duel.resume_request_opponent = true;
+        } else {
+            return Err(DuelError::NotParticipant.into());
+        }
+        
+        if duel.resume_request_creator && duel.resume_request_opponent {
+            if duel.expiry_mode == ExpiryMode::TimeBased {
+                let pause_duration = clock.unix_timestamp - duel.pause_start;
+                duel.end_time += pause_duration;
+                
+                if let Some(duel_timer) = &mut ctx.accounts.duel_timer {
+                    duel_timer.end_time = duel.end_time;
+                    duel_timer.extension_count = duel_timer.extension_count.saturating_add(1);
+                    duel_timer.total_extension_seconds += pause_duration;
+                }
+            }
+            duel.status = DuelStatus::Active;
+            duel.pause_start = 0;
+            duel.resume_request_creator = false;
+            duel.resume_request_opponent = false;
+            
+            msg!("Duel resumed. Trading period now ends at {}", duel.end_time);
+        }
+        
+        Ok(())
+    }
+
+    // Attach an immutable compliance notice to a duel (authority only)
+    pub fn issue_duel_notice(ctx: Context<IssueDuelNotice>, content: Vec<u8>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "issue_duel_notice",
+            ctx.accounts.authority.key(),
+            audit_args_hash(&content)?,
+        )?;
+        
+        require!(content.len() <= DUEL_NOTICE_MAX_LEN, DuelError::NoticeTooLong);
+        
+        let notice = &mut ctx.accounts.notice;
+        notice.duel = ctx.accounts.duel.key();
+        notice.content = [0; DUEL_NOTICE_MAX_LEN];
+        notice.content[..content.len()].copy_from_slice(&content);
+        notice.issued_by = ctx.accounts.authority.key();
+        notice.issued_at = Clock::get()?.unix_timestamp;
+        
+        ctx.accounts.duel.warning_notice_present = true;
+        
+        Ok(())
+    }
+
+    // Create a duel's comment forum (anyone can pay the rent)
+    pub fn create_duel_forum(ctx: Context<CreateDuelForum>) -> Result<()> {
+        let forum = &mut ctx.accounts.forum;
+        forum.duel = ctx.accounts.duel.key();
+        forum.comments = Vec::new();
+        forum.comment_count = 0;
+        Ok(())
+    }
+
+    // Append a comment to a duel's forum; comments cannot be edited
+    pub fn post_forum_comment(ctx: Context<PostForumComment>, content: Vec<u8>) -> Result<()> {
+        require!(
+            !content.is_empty() && content.len() <= FORUM_COMMENT_MAX_LEN,
+            DuelError::InvalidCommentLength
+        );
+        
+        let forum = &mut ctx.accounts.forum;
+        require!(forum.comments.len() < MAX_FORUM_COMMENTS, DuelError::ForumFull);
+        
+        let mut comment = ForumComment {
+            author: ctx.accounts.author.key(),
+            posted_at: Clock::get()?.unix_timestamp,
+            content: [0; FORUM_COMMENT_MAX_LEN],
+        };
+        comment.content[..content.len()].copy_from_slice(&content);
+        forum.comments.push(comment);
+        forum.comment_count += 1;
+        
+        Ok(())
+    }
+
+    // Blank out a forum comment (authority only). The slot is kept so comment
+    // indexes stay stable.
+    pub fn moderation_delete_comment(ctx: Context<ModerateForum>, idx: u16) -> Result<()> {
+        let comment = ctx
+            .accounts
+            .forum
+            .comments
+            .get_mut(idx as usize)
+            .ok_or(DuelError::CommentNotFound)?;
+        comment.content = [0; FORUM_COMMENT_MAX_LEN];
+        Ok(())
+    }
+
+    // Add a token to a pending duel's allowed list (creator only). The duel
+    // account grows by realloc once the reserved space is used; the creator
+    // pays the extra rent.
+    pub fn add_allowed_token(ctx: Context<AddAllowedToken>, new_token: Pubkey) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "add_allowed_token",
+            ctx.accounts.creator.key(),
+            audit_args_hash(&new_token)?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        require!(duel.status == DuelStatus::Pending, DuelError::InvalidStatus);
+        require!(!duel.allowed_tokens.contains(&new_token), DuelError::TokenAlreadyInList);
+        // The duel does not record the native SOL opt-in, so the native mint
+        // can only be allowed at creation
+        require!(
+            new_token != token::spl_token::native_mint::ID,
+            DuelError::NativeMintInAllowedTokens
+        );
+        
+        // Keep the list sorted, matching create_duel's normalization
+        let position = duel.allowed_tokens.binary_search(&new_token).unwrap_or_else(|i| i);
+        duel.allowed_tokens.insert(position, new_token);
+        require!(
+            duel.allowed_tokens.len() <= ctx.accounts.protocol.max_allowed_tokens as usize,
+            DuelError::TooManyAllowedTokens
+        );
+        Ok(())
+    }
+
+    // Create an empty price index for a duel that was made without one, such
+    // as a ranked or pool duel (creator or an authorized oracle, before
+    // trading starts)
+    pub fn init_price_index(ctx: Context<InitPriceIndex>) -> Result<()> {
+        check_price_index_editable(&ctx.accounts.duel)?;
+        
+        let price_index = &mut ctx.accounts.price_index;
+        price_index.duel = ctx.accounts.duel.key();
+        price_index.token_feeds = Vec::new();
+        Ok(())
+    }
+
+    // Map the duel's allowed tokens to the Pyth price accounts the oracle
+    // should value them with (creator or an authorized oracle, before trading
+    // starts)
+    pub fn set_price_index(
+        ctx: Context<SetPriceIndex>,
+        token_feeds: Vec<TokenPriceFeed>,
+    ) -> Result<()> {
+        let duel = &ctx.accounts.duel;
+        check_price_index_editable(duel)?;
+        require!(token_feeds.len() <= MAX_PRICE_FEEDS, DuelError::TooManyPriceFeeds);
+        
+        for (i, feed) in token_feeds.iter().enumerate() {
+            require!(
+                duel.allowed_tokens.contains(&feed.mint),
+                DuelError::FeedNotForAllowedToken
+            );
+            require!(
+                token_feeds[..i].iter().all(|other| other.mint != feed.mint),
+                DuelError::DuplicatePriceFeed
+            );
+        }
+        
+        ctx.accounts.price_index.token_feeds = token_feeds;
+        Ok(())
+    }
+
+    // Update trading positions (called by an authorized oracle)
+    pub fn update_positions(
+        ctx: Context<UpdatePositions>,
+        creator_value: u64,
+        opponent_value: u64,
+        args: PositionUpdateArgs,
+    ) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "update_positions",
+            ctx.accounts.oracle.key(),
+            audit_args_hash(&(creator_value, opponent_value, &args))?,
+        )?;
+        let PositionUpdateArgs {
+            creator_allocation,
+            opponent_allocation,
+            trade_proof,
+            is_final,
+            oracle_signature,
+            oracle_timestamp,
+        } = args;
+        
+        require!(
+            !ctx.accounts.protocol.is_instruction_paused(IX_UPDATE_POSITIONS),
+            DuelError::InstructionPaused
+        );
+        
+        let duel = &mut ctx.accounts.duel;
+        let clock = Clock::get()?;
+        
+        require!(duel.status != DuelStatus::Locked, DuelError::DuelLocked);
+        require!(duel.status != DuelStatus::Paused, DuelError::DuelPaused);
+        require!(duel.status == DuelStatus::Active, DuelError::InvalidStatus);
+        check_trading_open(duel, clock.unix_timestamp)?;
+        
+        // last_update_timestamp is the clock at the previous update, so this only
+        // trips on clock drift; it documents that updates are monotonic
+        if clock.unix_timestamp < duel.last_update_timestamp {
+            msg!(
+                "Oracle update at {} precedes last update at {}",
+                clock.unix_timestamp,
+                duel.last_update_timestamp
+            );
+            return err!(DuelError::OracleTimestampBackwards);
+        }
+        
+        require!(
+            !is_final || duel.expiry_mode == ExpiryMode::ManualOracle,
+            DuelError::InvalidExpiryMode
+        );
+        
+        // Tie the update to a DEX trade in one of the duel's tokens, made after
+        // the duel started (the timestamp is covered by the signature) and signed
+        // by a registered trade attestor
+        require!(
+            duel.allowed_tokens.contains(&trade_proof.token),
+            DuelError::TokenNotInAllowedList
+        );
+        require!(
+            ctx.accounts
+                .protocol
+                .trade_attestors
+                .contains(&Pubkey::new_from_array(trade_proof.trade_pubkey)),
+            DuelError::UnknownTradeAttestor
+        );
+        require!(
+            trade_proof.timestamp >= duel.start_time,
+            DuelError::TradeBeforeDuelStart
+        );
+        verify_trade_proof(
+            &ctx.accounts.instructions_sysvar.to_account_info(),
+            &duel.key(),
+            &trade_proof,
+        )?;
+        
+        // The authorized oracle must also have signed the reported values; the
+        // timestamp has to advance past the last update so old signatures cannot
+        // be replayed
+        require!(
+            oracle_timestamp > duel.last_update_timestamp && oracle_timestamp <= clock.unix_timestamp,
+            DuelError::InvalidOracleSignature
+        );
+        verify_oracle_signature(
+            &ctx.accounts.instructions_sysvar.to_account_info(),
+            &duel.key(),
+            &ctx.accounts.oracle.key(),
+            creator_value,
+            opponent_value,
+            oracle_timestamp,
+            &oracle_signature,
+        )?;
+        
+        // Enforce the creator's per-token weight limits on both portfolios
+        check_allocation_limits(&duel.creator_token_allocation, &creator_allocation)?;
+        check_allocation_limits(&duel.creator_token_allocation, &opponent_allocation)?;
+        
+        // Reject outliers relative to the previous update
+        if duel.last_update_timestamp > 0 {
+            let max_delta_bps = ctx.accounts.protocol.max_update_value_delta_bps;
+            check_update_delta(duel.creator_final_value, creator_value, max_delta_bps)?;
+            check_update_delta(duel.opponent_final_value, opponent_value, max_delta_bps)?;
+        }
+        
+        duel.creator_final_value = creator_value;
+        duel.opponent_final_value = opponent_value;
+        duel.last_update_timestamp = clock.unix_timestamp;
+        duel.creator_peak_value = duel.creator_peak_value.max(creator_value);
+        duel.opponent_peak_value = duel.opponent_peak_value.max(opponent_value);
+        duel.creator_trough_value = duel.creator_trough_value.min(creator_value);
+        duel.opponent_trough_value = duel.opponent_trough_value.min(opponent_value);
+        
+        // The oracle's final report closes trading on a manually expiring duel
+        if is_final {
+            duel.oracle_finalized = true;
+            msg!("Oracle finalized duel; ready for settlement");
+        }
+        
+        // Track how many updates each oracle has contributed
+        let oracle = ctx.accounts.oracle.key();
+        let update_count = match duel
+            .oracle_update_counts
+            .iter_mut()
+            .find(|entry| entry.oracle == oracle)
+        {
+            Some(entry) => {
+                entry.count += 1;
+                entry.count
+            }
+            None => {
+                require!(
+                    duel.oracle_update_counts.len() < MAX_DUEL_ORACLES,
+                    DuelError::TooManyDistinctOracles
+                );
+                duel.oracle_update_counts.push(OracleUpdateCount { oracle, count: 1 });
+                1
+            }
+        };
+        
+        if update_count % ORACLE_UPDATE_EVENT_INTERVAL == 0 {
+            emit!(OracleUpdateRecorded {
+                duel: duel.key(),
+                oracle,
+                update_count,
+            });
+        }
+        
+        let total_updates = total_oracle_updates(duel);
+        if total_updates == duel.required_oracle_updates as u32 {
+            emit!(UpdateCountReached {
+                duel: duel.key(),
+                count: total_updates,
+                required: duel.required_oracle_updates,
+            });
+        }
+        
+        emit!(PositionUpdate {
+            duel: duel.key(),
+            creator_value,
+            opponent_value,
+            timestamp: clock.unix_timestamp,
+        });
+        
+        // FirstToTarget duels settle as soon as either side reaches the target
+        if let Some(triggering_pnl) = early_settlement_pnl(duel, creator_value, opponent_value)? {
+            msg!("Early settlement triggered at {} bps", triggering_pnl);
+            
+            let missing = || error!(DuelError::SettlementAccountsRequired);
+            let duel_escrow = ctx.accounts.duel_escrow.as_ref().ok_or_else(missing)?;
+            let creator = ctx.accounts.creator.as_ref().ok_or_else(missing)?;
+            let opponent = ctx.accounts.opponent.as_ref().ok_or_else(missing)?;
+            let treasury = ctx.accounts.treasury.as_ref().ok_or_else(missing)?;
+            let system_program = ctx.accounts.system_program.as_ref().ok_or_else(missing)?;
+            let duel_fee = ctx.accounts.duel_fee.as_mut().ok_or_else(missing)?;
+            let duel_insight = ctx.accounts.duel_insight.as_mut().ok_or_else(missing)?;
+            let escrow_bump = ctx.bumps.duel_escrow.ok_or_else(missing)?;
+            
+            // Hold the re-entrancy guard across the payout CPIs below
+            require!(!duel.settling, DuelError::ReentrancyDetected);
+            duel.settling = true;
+            
+            let duel_key = duel.key();
+            let escrow_seeds = &[b"escrow".as_ref(), duel_key.as_ref(), &[escrow_bump]];
+            unwrap_wsol_escrow(
+                duel,
+                ctx.accounts.wsol_escrow.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+                &duel_escrow.to_account_info(),
+                &creator.to_account_info(),
+                &system_program.to_account_info(),
+                &[&escrow_seeds[..]],
+            )?;
+            let protocol = &mut ctx.accounts.protocol;
+            let outcome = execute_settlement(
+                duel,
+                duel_key,
+                escrow_bump,
+                protocol,
+                None,
+                SettlementAccounts {
+                    duel_escrow: &duel_escrow.to_account_info(),
+                    creator: &creator.to_account_info(),
+                    opponent: &opponent.to_account_info(),
+                    treasury: &treasury.to_account_info(),
+                    system_program: &system_program.to_account_info(),
+                },
+            )?;
+            
+            record_duel_fee(duel_fee, duel, duel_key, &outcome, clock.unix_timestamp);
+            record_participant_stats(
+                duel,
+                &mut ctx.accounts.creator_profile,
+                &mut ctx.accounts.opponent_profile,
+                &protocol.reputation_weights,
+            );
+            record_duel_insight(duel_insight, duel, duel_key, &outcome, clock.unix_timestamp)?;
+            record_audit(
+                &mut ctx.accounts.audit_log,
+                "early_settlement",
+                ctx.accounts.oracle.key(),
+                audit_args_hash(&triggering_pnl)?,
+            )?;
+            duel.settling = false;
+            
+            emit!(EarlySettlement {
+                duel: duel_key,
+                winner: duel.winner,
+                triggering_pnl,
+            });
+        }
+        
+        Ok(())
+    }
+
+    // Settle the duel and distribute winnings
+    pub fn settle_duel(ctx: Context<SettleDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "settle_duel",
+            ctx.accounts.settler.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        settle_expired_duel(ctx.accounts, ctx.bumps.duel_escrow, None, "settle_duel")
+    }
+
+    // Settle the duel, optionally with an authority-chosen winner that
+    // bypasses the PnL comparison. Anyone else must pass `None`.
+    pub fn settle_duel_v2(
+        ctx: Context<SettleDuel>,
+        force_winner: Option<DuelWinner>,
+    ) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "settle_duel_v2",
+            ctx.accounts.settler.key(),
+            audit_args_hash(&force_winner)?,
+        )?;
+        
+        if let Some(forced_winner) = force_winner {
+            require!(
+                ctx.accounts.settler.key() == ctx.accounts.protocol.authority,
+                DuelError::Unauthorized
+            );
+            require!(forced_winner != DuelWinner::None, DuelError::InvalidForcedWinner);
+            
+            emit!(ForceSettlementUsed {
+                duel: ctx.accounts.duel.key(),
+                forced_winner,
+                authority: ctx.accounts.settler.key(),
+            });
+        }
+        
+        settle_expired_duel(ctx.accounts, ctx.bumps.duel_escrow, force_winner, "settle_duel_v2")
+    }
+
+    // Settle the duel and close it, along with its fee, insight and timer
+    // records, in one transaction, returning the rent to the creator (creator
+    // only). Closing the records here skips their retention period.
+    pub fn settle_and_close_duel(ctx: Context<SettleAndCloseDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.settle.audit_log,
+            "settle_and_close_duel",
+            ctx.accounts.settle.settler.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        let settle = &mut ctx.accounts.settle;
+        require_keys_eq!(settle.creator.key(), settle.duel.creator, DuelError::NotParticipant);
+        require_keys_eq!(settle.settler.key(), settle.duel.creator, DuelError::Unauthorized);
+        
+        settle_expired_duel(settle, ctx.bumps.settle.duel_escrow, None, "settle_and_close_duel")?;
+        
+        // The settler is the creator, so the fee and insight records it just paid
+        // for go back to it too. The audit log is kept, which also stops the
+        // closed duel's nonce from being reused: CreateDuel would fail to init it.
+        let creator = settle.creator.to_account_info();
+        let mut closed = vec![
+            settle.duel.to_account_info(),
+            settle.duel_fee.to_account_info(),
+            settle.duel_insight.to_account_info(),
+        ];
+        if let Some(duel_timer) = &ctx.accounts.duel_timer {
+            closed.push(duel_timer.to_account_info());
+        }
+        let rent_returned = closed_rent(&closed)?;
+        settle.duel.close(creator.clone())?;
+        settle.duel_fee.close(creator.clone())?;
+        settle.duel_insight.close(creator.clone())?;
+        if let Some(duel_timer) = &ctx.accounts.duel_timer {
+            duel_timer.close(creator)?;
+        }
+        
+        emit!(DuelClosed {
+            duel: settle.duel.key(),
+            rent_returned,
+        });
+        
+        Ok(())
+    }
+
+    // Settle up to MAX_BULK_SETTLE expired duels in one transaction. Remaining
+    // accounts are passed as (duel, escrow, creator, opponent, treasury, audit log)
+    // groups in the same order as `duel_pubkeys`; any failure reverts the whole batch.
+    pub fn bulk_settle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BulkSettle<'info>>,
+        duel_pubkeys: Vec<Pubkey>,
+    ) -> Result<()> {
+        let compute_start = log_compute!();
+        
+        require!(
+            !ctx.accounts.protocol.is_instruction_paused(IX_SETTLE_DUEL),
+            DuelError::InstructionPaused
+        );
+        require!(duel_pubkeys.len() <= MAX_BULK_SETTLE, DuelError::BulkSettleTooMany);
+        require!(
+            ctx.remaining_accounts.len() == duel_pubkeys.len() * BULK_SETTLE_GROUP_SIZE,
+            DuelError::InvalidBulkAccounts
+        );
+        
+        let protocol = &mut ctx.accounts.protocol;
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let settler = ctx.accounts.settler.key();
+        let clock = Clock::get()?;
+        
+        for (duel_pubkey, group) in duel_pubkeys
+            .iter()
+            .zip(ctx.remaining_accounts.chunks(BULK_SETTLE_GROUP_SIZE))
+        {
+            let [duel_info, escrow_info, creator_info, opponent_info, treasury_info, audit_info] = group
+            else {
+                return Err(DuelError::InvalidBulkAccounts.into());
+            };
+            require_keys_eq!(duel_info.key(), *duel_pubkey, DuelError::InvalidBulkAccounts);
+            check_duel_account_data(duel_info)?;
+            
+            let mut duel: Account<Duel> = Account::try_from(duel_info)?;
+            
+            require!(duel.status == DuelStatus::Active, DuelError::InvalidStatus);
+            check_settleable(&duel, clock.unix_timestamp, protocol.max_manual_duration)?;
+            // WSOL escrows need the token program to unwrap; settle those individually
+            require!(!duel.use_wsol, DuelError::WsolEscrowRequired);
+            
+            let (escrow_key, escrow_bump) =
+                Pubkey::find_program_address(&[b"escrow", duel_pubkey.as_ref()], &crate::ID);
+            require_keys_eq!(escrow_info.key(), escrow_key, DuelError::InvalidBulkAccounts);
+            require_keys_eq!(creator_info.key(), duel.creator, DuelError::InvalidBulkAccounts);
+            require_keys_eq!(opponent_info.key(), duel.opponent, DuelError::InvalidBulkAccounts);
+            require_keys_eq!(treasury_info.key(), protocol.treasury, DuelError::InvalidBulkAccounts);
+            
+            let (audit_key, _) =
+                Pubkey::find_program_address(&[b"audit", duel_pubkey.as_ref()], &crate::ID);
+            require_keys_eq!(audit_info.key(), audit_key, DuelError::InvalidBulkAccounts);
+            let mut audit_log: Account<DuelAuditLog> = Account::try_from(audit_info)?;
+            record_audit(&mut audit_log, "bulk_settle", settler, audit_args_hash(&duel_pubkeys)?)?;
+            audit_log.exit(&crate::ID)?;
+            
+            let forced_winner = expired_settlement_winner(&duel, None);
+            execute_settlement(
+                &mut duel,
+                *duel_pubkey,
+                escrow_bump,
+                protocol,
+                forced_winner,
+                SettlementAccounts {
+                    duel_escrow: escrow_info,
+                    creator: creator_info,
+                    opponent: opponent_info,
+                    treasury: treasury_info,
+                    system_program: &system_program,
+                },
+            )?;
+            
+            duel.exit(&crate::ID)?;
+        }
+        
+        log_compute!("bulk_settle", compute_start);
+        
+        Ok(())
+    }
+
+    // Cancel any unsettled duel and refund deposited stakes (authority only).
+    // `reason_hash` is the SHA-256 of an off-chain explanation.
+    pub fn emergency_cancel_duel(
+        ctx: Context<EmergencyCancelDuel>,
+        reason_hash: [u8; 32],
+    ) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "emergency_cancel_duel",
+            ctx.accounts.authority.key(),
+            audit_args_hash(&reason_hash)?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        
+        require!(
+            !matches!(duel.status, DuelStatus::Settled | DuelStatus::Cancelled),
+            DuelError::CannotCancel
+        );
+        
+        let duel_key = duel.key();
+        let escrow_seeds = &[b"escrow".as_ref(), duel_key.as_ref(), &[ctx.bumps.duel_escrow]];
+        unwrap_wsol_escrow(
+            duel,
+            ctx.accounts.wsol_escrow.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.duel_escrow.to_account_info(),
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            &[&escrow_seeds[..]],
+        )?;
+        if duel.creator_stake_deposited && duel.opponent_stake_deposited {
+            release_active_stake(&mut ctx.accounts.protocol, duel);
+        }
+        track_duel_status(&mut ctx.accounts.protocol, Some(duel.status), DuelStatus::Cancelled);
+        refund_deposits(
+            duel,
+            duel_key,
+            ctx.bumps.duel_escrow,
+            &ctx.accounts.duel_escrow.to_account_info(),
+            &ctx.accounts.creator.to_account_info(),
+            ctx.accounts.opponent.as_ref().map(|o| o.to_account_info()).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        duel.status = DuelStatus::Cancelled;
+        
+        emit!(EmergencyDuelCancelled {
+            duel: duel_key,
+            cancelled_by: ctx.accounts.authority.key(),
+            reason_hash,
+        });
+        
+        Ok(())
+    }
+
+    // Reclaim rent from a settled duel's fee record after the retention period
+    pub fn close_duel_fee(ctx: Context<CloseDuelFee>) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp
+                >= ctx.accounts.duel_fee.settled_at + SETTLEMENT_RECORD_RETENTION_SECONDS,
+            DuelError::RecordRetentionActive
+        );
+        Ok(())
+    }
+
+    // Reclaim rent from a settled duel's insight record after the retention period
+    pub fn close_duel_insight(ctx: Context<CloseDuelInsight>) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp
+                >= ctx.accounts.duel_insight.settled_at + SETTLEMENT_RECORD_RETENTION_SECONDS,
+            DuelError::RecordRetentionActive
+        );
+        Ok(())
+    }
+
+    // Reclaim a duel timer's rent once the duel has settled or been cancelled
+    pub fn close_duel_timer(ctx: Context<CloseDuelTimer>) -> Result<()> {
+        require!(
+            matches!(ctx.accounts.duel.status, DuelStatus::Settled | DuelStatus::Cancelled),
+            DuelError::InvalidStatus
+        );
+        Ok(())
+    }
+
+    // Cancel a pending duel (creator only) or an accepted duel (either
+    // participant), refunding any stakes already deposited
+    pub fn cancel_duel(ctx: Context<CancelDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "cancel_duel",
+            ctx.accounts.canceller.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        let canceller = ctx.accounts.canceller.key();
+        
+        match duel.status {
+            DuelStatus::Pending => {
+                require!(canceller == duel.creator, DuelError::CannotCancelOtherCreator);
+            }
+            DuelStatus::Accepted => {
+                require!(
+                    canceller == duel.creator || canceller == duel.opponent,
+                    DuelError::NotParticipant
+                );
+            }
+            DuelStatus::Active | DuelStatus::Locked | DuelStatus::Paused | DuelStatus::PendingOracle => {
+                return Err(DuelError::CannotCancelActive.into());
+            }
+            DuelStatus::Settled | DuelStatus::Cancelled => {
+                return Err(DuelError::CannotCancelSettled.into());
+            }
+        }
+        
+        // Reclaim the WSOL escrow, if any, before refunding from the SOL escrow
+        let duel_key = duel.key();
+        let escrow_seeds = &[b"escrow".as_ref(), duel_key.as_ref(), &[ctx.bumps.duel_escrow]];
+        unwrap_wsol_escrow(
+            duel,
+            ctx.accounts.wsol_escrow.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.duel_escrow.to_account_info(),
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            &[&escrow_seeds[..]],
+        )?;
+        
+        // Pending duels have no deposits, so this only refunds accepted ones
+        refund_deposits(
+            duel,
+            duel_key,
+            ctx.bumps.duel_escrow,
+            &ctx.accounts.duel_escrow.to_account_info(),
+            &ctx.accounts.creator.to_account_info(),
+            ctx.accounts.opponent.as_ref().map(|o| o.to_account_info()).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        track_duel_status(&mut ctx.accounts.protocol, Some(duel.status), DuelStatus::Cancelled);
+        duel.status = DuelStatus::Cancelled;
+        
+        Ok(())
+    }
+
+    // Devnet-only load testing: create a funded duel that is already Active,
+    // skipping accept, deposits and oracle initialization
+    #[cfg(feature = "devnet")]
+    pub fn create_test_duel(
+        ctx: Context<CreateTestDuel>,
+        nonce: u64,
+        stake_amount: u64,
+        duration_seconds: i64,
+        creator_starting_value: u64,
+        opponent_starting_value: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let protocol = &mut ctx.accounts.protocol;
+        
+        let mut duel = accepted_duel(
+            ctx.accounts.creator.key(),
+            ctx.accounts.opponent.key(),
+            stake_amount,
+            duration_seconds,
+            protocol.default_allowed_tokens.clone(),
+            clock.unix_timestamp,
+        );
+        duel.nonce = nonce;
+        duel.creator_stake_deposited = true;
+        duel.opponent_stake_deposited = true;
+        duel.creator_deposit_at = clock.unix_timestamp;
+        duel.opponent_deposit_at = clock.unix_timestamp;
+        duel.both_deposited_at = clock.unix_timestamp;
+        add_active_stake(protocol, &duel)?;
+        start_duel(&mut duel, clock.unix_timestamp, creator_starting_value, opponent_starting_value);
+        
+        // The creator funds both sides so settlement has a real escrow to pay out
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.creator.to_account_info(),
+                    to: ctx.accounts.duel_escrow.to_account_info(),
+                },
+            ),
+            stake_amount.checked_mul(2).ok_or(DuelError::Overflow)?,
+        )?;
+        
+        ctx.accounts.duel.set_inner(duel);
+        protocol.total_duels = protocol
+            .total_duels
+            .checked_add(1)
+            .ok_or(DuelError::TotalDuelsOverflow)?;
+        
+        let audit_log = &mut ctx.accounts.audit_log;
+        audit_log.duel = ctx.accounts.duel.key();
+        record_audit(
+            audit_log,
+            "create_test_duel",
+            ctx.accounts.creator.key(),
+            audit_args_hash(&(
+                nonce,
+                stake_amount,
+                duration_seconds,
+                creator_starting_value,
+                opponent_starting_value,
+            ))?,
+        )
+    }
+
+    // Devnet-only load testing: settle an Active duel now, ignoring its end time
+    #[cfg(feature = "devnet")]
+    pub fn instant_settle_test_duel(ctx: Context<SettleDuel>) -> Result<()> {
+        record_audit(
+            &mut ctx.accounts.audit_log,
+            "instant_settle_test_duel",
+            ctx.accounts.settler.key(),
+            audit_args_hash(&())?,
+        )?;
+        
+        let duel = &mut ctx.accounts.duel;
+        require!(duel.status == DuelStatus::Active, DuelError::InvalidStatus);
+        duel.expiry_mode = ExpiryMode::TimeBased;
+        duel.end_time = Clock::get()?.unix_timestamp;
+        
+        settle_expired_duel(ctx.accounts, ctx.bumps.duel_escrow, None, "instant_settle_test_duel")
+    }
+
+    // Devnet-only load testing: create a profile with a chosen ELO rating
+    #[cfg(feature = "devnet")]
+    pub fn create_test_profile(
+        ctx: Context<CreatePlayerProfile>,
+        display_name: Vec<u8>,
+        elo_rating: u32,
+    ) -> Result<()> {
+        let profile = &mut ctx.accounts.profile;
+        profile.wallet = ctx.accounts.wallet.key();
+        profile.display_name = to_display_name(&display_name)?;
+        profile.created_at = Clock::get()?.unix_timestamp;
+        profile.elo_rating = elo_rating;
+        profile.accepted_terms_hash = [0; 32];
+        profile.duels_participated = 0;
+        profile.win_streak = 0;
+        profile.total_volume = 0;
+        profile.reputation_score = 0;
+        Ok(())
+    }
+}
+
+// Account structures
+#[account]
+pub struct Protocol {
+    pub authority: Pubkey,
     pub treasury: Pubkey,
     pub fee_bps: u16, // Basis points (100 = 1%)
+    // Overflow policy: total_duels is a count clients rely on, so it is checked
+    // and fails with TotalDuelsOverflow; total_volume and total_fees_collected
+    // are informational only and saturate at u64::MAX
+    pub total_duels: u64,
+    pub total_volume: u64,
+    pub min_duel_duration: i64,
+    pub max_duel_duration: i64,
+    pub paused_instructions: u16, // Bitmask indexed by IX_* constants
+    pub stake_lock_period_seconds: i64,
+    pub category_enabled: [bool; DUEL_CATEGORY_COUNT],
+    pub min_client_version: u32,
+    pub default_allowed_tokens: Vec<Pubkey>,
+    pub ranked_mode_enabled: bool,
+    pub ranked_min_stake_factor: u64, // Lamports of stake required per 1000 ELO
+    pub elo_match_range: u32,
+    pub queue_timeout_seconds: i64,
+    pub creator_quick_accept_bonus_bps: u16,
+    pub bonus_condition: QuickAcceptBonusCondition,
+    pub whitepaper_hash: [u8; 32], // SHA-256 of the published whitepaper / spec
+    pub terms_hash: [u8; 32],      // SHA-256 of the current terms of service
+    pub max_update_value_delta_bps: u32,
+    pub creation_fee_lamports: u64,
+    pub total_fees_collected: u64, // Creation and settlement fees sent to the treasury
+    pub total_pools: u64,
+    pub pending_treasury: Pubkey, // Default when no change is proposed
+    pub treasury_change_proposed_at: i64,
+    pub max_deposit_gap_seconds: i64, // 0 = no limit
+    pub oracle_init_deadline_seconds: i64,
+    pub max_manual_duration: i64, // Safety cap for ManualOracle duels
+    pub total_active_stake: u64, // Lamports escrowed in fully funded, unsettled duels
+    pub peak_active_stake: u64,
+    pub loyalty_discount_threshold: u32,
+    pub loyalty_discount_bps: u16,
+    pub reputation_weights: [u64; 3],
+    pub total_fee_proposals: u64,
+    pub absolute_pnl_duration_threshold: i64, // 0 = always compare percentages
+    pub max_allowed_tokens: u8,
+    pub min_update_interval_seconds: i64,
+    pub require_profile_for_participation: bool,
+    pub initialized: bool,
+    pub authorized_oracles: Vec<Pubkey>, // Only these keys may report positions
+    pub trade_attestors: Vec<Pubkey>, // Only these keys may sign trade proofs
+    pub pending_duels: u64,  // Duels awaiting an opponent
+    pub accepted_duels: u64, // Accepted duels awaiting both stakes
+}
+
+impl Protocol {
+    pub const fn space() -> usize {
+        8 + 32 + 32 + 2 + 8 + 8 + 8 + 8 + 2 + 8 + DUEL_CATEGORY_COUNT + 4
+            + 4 + (32 * MAX_DEFAULT_ALLOWED_TOKENS) + 1 + 8 + 4 + 8 + 2 + 1 + 32 + 32 + 4 + 8 + 8 + 8 + 32 + 8 + 8
+            + 8 + 8 + 8 + 8 + 4 + 2 + (8 * 3) + 8 + 8 + 1 + 8 + 1 + 1
+            + 4 + (32 * MAX_AUTHORIZED_ORACLES) + 4 + (32 * MAX_TRADE_ATTESTORS) + 8 + 8
+    }
+
+    pub fn is_instruction_paused(&self, instruction_idx: u8) -> bool {
+        self.paused_instructions & (1 << instruction_idx) != 0
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct Duel {
+    pub creator: Pubkey,
+    pub opponent: Pubkey,
+    pub stake_amount: u64,
+    pub created_at: i64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub duration: i64,
+    pub status: DuelStatus,
+    pub winner: DuelWinner,
+    pub creator_stake_deposited: bool,
+    pub opponent_stake_deposited: bool,
+    pub allowed_tokens: Vec<Pubkey>,
+    pub creator_starting_value: u64,
+    pub opponent_starting_value: u64,
+    pub creator_final_value: u64,
+    pub opponent_final_value: u64,
+    pub stake_locked_until: i64,
+    pub pause_request_creator: bool,
+    pub pause_request_opponent: bool,
+    pub resume_request_creator: bool,
+    pub resume_request_opponent: bool,
+    pub pause_start: i64,
+    pub category: DuelCategory,
+    pub oracle_update_counts: Vec<OracleUpdateCount>,
+    pub creator_alias: [u8; 32], // Cached PlayerProfile display names
+    pub opponent_alias: [u8; 32],
+    pub warning_notice_present: bool, // A DuelNotice has been issued
+    pub creator_token_allocation: Vec<TokenAllocationLimit>,
+    // Deposit times are informational: the trading clock only starts at oracle
+    // init, after both deposits, so both players always start together
+    pub creator_deposit_at: i64,
+    pub opponent_deposit_at: i64,
+    pub creator_quick_accept_bonus: u64,
+    pub win_condition: WinCondition,
+    pub last_update_timestamp: i64, // 0 until the first position update
+    pub use_wsol: bool, // Stakes are held as WSOL in the duel's token escrow
+    pub both_deposited_at: i64,
+    pub oracle_init_deadline: i64, // Set on entering PendingOracle
+    // Portfolio extremes reported by the oracle, seeded with the starting values
+    pub creator_peak_value: u64,
+    pub opponent_peak_value: u64,
+    pub creator_trough_value: u64,
+    pub opponent_trough_value: u64,
+    pub expiry_mode: ExpiryMode,
+    pub oracle_finalized: bool, // ManualOracle only: the oracle sent its final update
+    pub nonce: u64, // PDA seed chosen by the creator (the joining player for ranked duels); 0 for pool duels
+    pub settling: bool, // Re-entrancy guard held while a deposit or settlement is in progress
+    pub required_oracle_updates: u8, // Position updates needed before the duel can settle
+    // Loyalty discount eligibility, fixed when each player joins
+    pub creator_loyal: bool,
+    pub opponent_loyal: bool,
+}
+
+impl Duel {
+    pub const fn space() -> usize {
+        8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 1 + 1 + 1 + 1 + 4 + (32 * DUEL_RESERVED_ALLOWED_TOKENS) + 8 + 8 + 8 + 8 + 8
+            + 1 + 1 + 1 + 1 + 8 + 1 + 4 + (36 * MAX_DUEL_ORACLES) + 32 + 32 + 1
+            + 4 + (34 * MAX_TOKEN_ALLOCATION_LIMITS) + 8 + 8 + 8 + 9 + 8 + 1 + 8 + 8
+            + 8 + 8 + 8 + 8 + 1 + 1 + 8 + 1 + 1 + 1 + 1
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct OracleUpdateCount {
+    pub oracle: Pubkey,
+    pub count: u32,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct TokenAllocationLimit {
+    pub mint: Pubkey,
+    pub max_weight_bps: u16, // Maximum share of portfolio value, 10000 = 100%
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct TradeProof {
+    pub trade_signature: [u8; 64],
+    pub trade_pubkey: [u8; 32], // Registered trade attestor that signed the trade
+    pub trade_amount: u64,
+    pub token: Pubkey,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct TokenPosition {
+    pub mint: Pubkey,
+    pub value: u64,
+}
+
+// create_duel parameters beyond the stake, duration and token list
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct CreateDuelArgs {
+    pub category: DuelCategory,
+    pub client_version: u32,
+    pub creator_token_allocation: Vec<TokenAllocationLimit>,
+    pub win_condition: WinCondition,
+    pub use_wsol: bool,
+    pub expiry_mode: ExpiryMode, // ManualOracle duels end on the oracle's final update
+    pub duel_uses_sol_natively: bool, // Required to list the native SOL mint
+}
+
+// update_positions parameters beyond the two portfolio values
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct PositionUpdateArgs {
+    pub creator_allocation: Vec<TokenPosition>,
+    pub opponent_allocation: Vec<TokenPosition>,
+    pub trade_proof: TradeProof,
+    pub is_final: bool, // Ends trading on a ManualOracle duel
+    pub oracle_signature: [u8; 64],
+    pub oracle_timestamp: i64,
+}
+
+#[account]
+pub struct DuelNotice {
+    pub duel: Pubkey,
+    pub content: [u8; DUEL_NOTICE_MAX_LEN], // UTF-8, zero padded
+    pub issued_by: Pubkey,
+    pub issued_at: i64,
+}
+
+// All comments on a duel in one account, so clients load them with one fetch
+#[account]
+pub struct DuelForum {
+    pub duel: Pubkey,
+    pub comments: Vec<ForumComment>,
+    pub comment_count: u16, // Comments ever posted, including moderated ones
+}
+
+impl DuelForum {
+    pub const fn space() -> usize {
+        8 + 32 + 4 + ((32 + 8 + FORUM_COMMENT_MAX_LEN) * MAX_FORUM_COMMENTS) + 2
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct ForumComment {
+    pub author: Pubkey,
+    pub posted_at: i64,
+    pub content: [u8; FORUM_COMMENT_MAX_LEN], // UTF-8, zero padded; all zeros once moderated
+}
+
+// Append-only history of the calls that modified a duel. Once full, the
+// oldest entry is overwritten.
+#[account]
+pub struct DuelAuditLog {
+    pub duel: Pubkey,
+    pub entries: Vec<AuditEntry>,
+    pub next_index: u16, // Slot the next entry is written to once the log is full
+    pub total_entries: u32, // Entries ever recorded, including overwritten ones
+}
+
+impl DuelAuditLog {
+    pub const fn space() -> usize {
+        8 + 32 + 4 + (104 * MAX_AUDIT_ENTRIES) + 2 + 4
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub instruction_name: [u8; 32], // UTF-8, zero padded
+    pub signer: Pubkey, // Caller that signed the instruction
+    pub timestamp: i64,
+    pub instruction_args_hash: [u8; 32], // SHA-256 of the borsh-serialized arguments
+}
+
+// Price feed for each allowed token, read by the oracle when valuing portfolios
+#[account]
+pub struct DuelPriceIndex {
+    pub duel: Pubkey,
+    pub token_feeds: Vec<TokenPriceFeed>,
+}
+
+impl DuelPriceIndex {
+    pub const fn space() -> usize {
+        8 + 32 + 4 + (64 * MAX_PRICE_FEEDS)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct TokenPriceFeed {
+    pub mint: Pubkey,
+    pub price_account: Pubkey, // Pyth price account
+}
+
+// Analytics computed once at settlement; never modified afterwards
+#[account]
+pub struct DuelInsight {
+    pub duel: Pubkey,
+    pub creator: Pubkey,
+    pub creator_pnl_bps: i64,
+    pub opponent_pnl_bps: i64,
+    pub pnl_margin_bps: i64,
+    pub winner_return_pct: i64, // Net return on stake; each side's return on a draw
+    pub loser_loss_pct: i64,
+    pub duration_days: u16,
+    pub update_count: u32, // Position updates across all oracles
+    pub settled_at: i64,
+    // Best and worst points relative to the starting value; drawdowns are positive
+    pub creator_max_gain_bps: i64,
+    pub creator_max_drawdown_bps: i64,
+    pub opponent_max_gain_bps: i64,
+    pub opponent_max_drawdown_bps: i64,
+}
+
+impl DuelInsight {
+    pub const fn space() -> usize {
+        8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 2 + 4 + 8 + 8 + 8 + 8 + 8
+    }
+}
+
+// Countdown state split out of Duel so clients can subscribe to a small account
+#[account]
+pub struct DuelTimer {
+    pub duel: Pubkey,
+    pub rent_payer: Pubkey, // The depositor that funded the duel; receives the rent on close
+    pub start_time: i64, // 0 until the oracle starts the duel
+    pub end_time: i64, // 0 until started, and always for ManualOracle duels
+    pub duration: i64,
+    pub extension_count: u8,
+    pub total_extension_seconds: i64,
+}
+
+impl DuelTimer {
+    pub const fn space() -> usize {
+        8 + 32 + 32 + 8 + 8 + 8 + 1 + 8
+    }
+}
+
+#[account]
+pub struct DuelFee {
+    pub duel: Pubkey,
+    pub creator: Pubkey,
+    pub total_fee: u64,
+    pub protocol_share: u64,
+    pub referrer_share: u64,
+    pub super_referrer_share: u64,
+    pub insurance_share: u64,
+    pub staker_share: u64,
+    pub effective_fee_bps: u16,
+    pub settled_at: i64,
+}
+
+impl DuelFee {
+    pub const fn space() -> usize {
+        8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 2 + 8
+    }
+}
+
+#[account]
+pub struct CategoryLeaderboard {
+    pub category: DuelCategory,
+    pub entries: Vec<CategoryLeaderboardEntry>, // Sorted by ELO, highest first
+}
+
+impl CategoryLeaderboard {
+    pub const fn space() -> usize {
+        8 + 1 + 4 + (40 * CATEGORY_LEADERBOARD_SIZE)
+    }
+}
+
+#[account]
+pub struct DuelHeatmap {
+    pub hourly_counts: [u32; HEATMAP_BUCKETS],  // Duels created per hour of the week
+    pub hourly_settles: [u32; HEATMAP_BUCKETS], // Duels settled per hour of the week
+}
+
+impl DuelHeatmap {
+    pub const fn space() -> usize {
+        8 + (4 * HEATMAP_BUCKETS) + (4 * HEATMAP_BUCKETS)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct CategoryLeaderboardEntry {
+    pub player: Pubkey,
+    pub elo: u32,
+    pub wins: u32,
+}
+
+#[account]
+pub struct DuelPool {
+    pub pool_id: u64,
+    pub creator: Pubkey,
+    pub max_participants: u8,
+    pub stake_per_duel: u64,
+    pub duration: i64,
+    pub participants: Vec<Pubkey>,
+    pub duel_matrix: Vec<Pubkey>, // Pairwise duels, ordered (0,1), (0,2), ..., (1,2), ...
+    pub scores: Vec<u32>,         // Wins per participant
+    pub results_recorded: Vec<bool>, // Parallel to duel_matrix
+    pub started: bool,
+    pub finalized: bool,
+    pub winner: Pubkey,
+}
+
+impl DuelPool {
+    pub const fn space() -> usize {
+        8 + 8 + 32 + 1 + 8 + 8 + 4 + (32 * MAX_POOL_PARTICIPANTS) + 4 + (32 * MAX_POOL_DUELS)
+            + 4 + (4 * MAX_POOL_PARTICIPANTS) + 4 + MAX_POOL_DUELS + 1 + 1 + 32
+    }
+}
+
+#[account]
+pub struct RankedQueue {
+    pub entries: Vec<RankedQueueEntry>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct RankedQueueEntry {
+    pub wallet: Pubkey,
+    pub elo: u32,
+    pub stake_amount: u64,
+    pub joined_at: i64,
+    pub loyal: bool, // Loyalty discount eligibility when the player joined
+}
+
+#[account]
+pub struct PlayerProfile {
+    pub wallet: Pubkey,
+    pub display_name: [u8; 32], // UTF-8, zero padded
+    pub created_at: i64,
+    pub elo_rating: u32,
+    pub accepted_terms_hash: [u8; 32],
+    pub duels_participated: u32, // Settled duels, counted when the profile is passed to settlement
+    pub win_streak: u32, // Consecutive wins; reset by a loss or draw
+    pub total_volume: u64, // Lamports staked across settled duels
+    pub reputation_score: u64,
+}
+
+#[account]
+pub struct FeeProposal {
+    pub proposal_id: u64,
+    pub proposed_fee_bps: u16,
+    pub proposer: Pubkey,
+    pub votes_for: u32,
+    pub votes_against: u32,
+    pub voting_ends_at: i64,
+    pub executed: bool,
+}
+
+impl FeeProposal {
+    pub const fn space() -> usize {
+        8 + 8 + 2 + 32 + 4 + 4 + 8 + 1
+    }
+}
+
+#[account]
+pub struct FeeVote {
+    pub proposal: Pubkey,
+    pub voter: Pubkey,
+    pub approve: bool,
+}
+
+impl FeeVote {
+    pub const fn space() -> usize {
+        8 + 32 + 32 + 1
+    }
+}
+
+#[account]
+pub struct FriendList {
+    pub wallet: Pubkey,
+    pub friends: Vec<Pubkey>,
+    pub pending_requests: Vec<Pubkey>, // Incoming requests awaiting a response
+}
+
+#[account]
+pub struct DuelTemplate {
+    pub creator: Pubkey,
+    pub template_id: u64,
+    pub name: [u8; 32],
+    pub stake_amount: u64,
+    pub duration_seconds: i64,
+    pub allowed_tokens: Vec<Pubkey>, // Empty = protocol defaults
+    pub category: DuelCategory,
+    pub use_count: u32,
+}
+
+impl DuelTemplate {
+    pub const fn space() -> usize {
+        8 + 32 + 8 + 32 + 8 + 8 + 4 + (32 * MAX_TEMPLATE_ALLOWED_TOKENS) + 1 + 4
+    }
+}
+
+impl FriendList {
+    pub const fn space() -> usize {
+        8 + 32 + 4 + (32 * MAX_FRIENDS) + 4 + (32 * MAX_PENDING_FRIEND_REQUESTS)
+    }
+}
+
+impl PlayerProfile {
+    pub const fn space() -> usize {
+        8 + 32 + 32 + 8 + 4 + 32 + 4 + 4 + 8 + 8
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Default)]
+pub enum DuelStatus {
+    #[default]
+    Pending,
+    Accepted,
+    Active,
+    Settled,
+    Cancelled,
+    Locked,
+    Paused,
+    PendingOracle, // Funded, waiting for the oracle's starting values
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Default)]
+pub enum DuelCategory {
+    #[default]
+    Meme,
+    BlueChip,
+    Defi,
+    Nft,
+    Custom,
+}
+
+pub const DUEL_CATEGORY_COUNT: usize = 5;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum QuickAcceptBonusCondition {
+    Always,
+    CreatorWins,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Default)]
+pub enum WinCondition {
+    #[default]
+    HighestPnl, // Compare PnL once the duel expires
+    FirstToTarget { target_pnl_bps: i64 }, // Settle as soon as either side reaches the target
+    HigherAbsoluteReturn, // Compare the change in portfolio value once the duel expires
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Default)]
+pub enum ExpiryMode {
+    #[default]
+    TimeBased, // Trading ends at end_time
+    ManualOracle, // No end_time; the oracle's final update ends trading
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Default)]
+pub enum DuelWinner {
+    #[default]
+    None,
+    Creator,
+    Opponent,
+    Draw,
+}
+
+// Zero-filled account data must decode as a fresh duel, so the defaults have
+// to stay the zeroth variants
+const _: () = assert!(DuelStatus::Pending as u8 == 0 && DuelWinner::None as u8 == 0);
+
+// Context structs
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = Protocol::space(),
+        seeds = [b"protocol"],
+        bump
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    /// CHECK: Treasury account for fees
+    pub treasury: UncheckedAccount<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateProtocol<'info> {
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateFeeProposal<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = FeeProposal::space(),
+        seeds = [b"fee_proposal", protocol.total_fee_proposals.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub proposal: Account<'info, FeeProposal>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(proposal_id: u64)]
+pub struct VoteOnFeeProposal<'info> {
+    #[account(
+        mut,
+        seeds = [b"fee_proposal", proposal_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub proposal: Account<'info, FeeProposal>,
+    
+    #[account(
+        init,
+        payer = voter,
+        space = FeeVote::space(),
+        seeds = [b"fee_vote", proposal.key().as_ref(), voter.key().as_ref()],
+        bump
+    )]
+    pub vote: Account<'info, FeeVote>,
+    
+    #[account(seeds = [b"profile", voter.key().as_ref()], bump)]
+    pub profile: Account<'info, PlayerProfile>,
+    
+    #[account(mut)]
+    pub voter: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(proposal_id: u64)]
+pub struct ExecuteFeeProposal<'info> {
+    #[account(
+        mut,
+        seeds = [b"fee_proposal", proposal_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub proposal: Account<'info, FeeProposal>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseProtocol<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut, close = authority, seeds = [b"heatmap"], bump)]
+    pub heatmap: Option<Account<'info, DuelHeatmap>>,
+    
+    #[account(mut, seeds = [b"ranked_queue"], bump)]
+    /// CHECK: Closed here if it was ever created; must hold no queued players
+    pub ranked_queue: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfirmTreasuryChange<'info> {
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(address = protocol.pending_treasury @ DuelError::Unauthorized)]
+    pub new_treasury: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreatePlayerProfile<'info> {
+    #[account(
+        init,
+        payer = wallet,
+        space = PlayerProfile::space(),
+        seeds = [b"profile", wallet.key().as_ref()],
+        bump
+    )]
+    pub profile: Account<'info, PlayerProfile>,
+    
+    #[account(mut)]
+    pub wallet: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePlayerProfile<'info> {
+    #[account(
+        mut,
+        seeds = [b"profile", wallet.key().as_ref()],
+        bump,
+        has_one = wallet @ DuelError::Unauthorized
+    )]
+    pub profile: Account<'info, PlayerProfile>,
+    
+    pub wallet: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptTerms<'info> {
+    #[account(
+        mut,
+        seeds = [b"profile", wallet.key().as_ref()],
+        bump,
+        has_one = wallet @ DuelError::Unauthorized
+    )]
+    pub profile: Account<'info, PlayerProfile>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    pub wallet: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(player: Pubkey)]
+pub struct ComputeReputationScore<'info> {
+    #[account(mut, seeds = [b"profile", player.as_ref()], bump)]
+    pub profile: Account<'info, PlayerProfile>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+}
+
+#[derive(Accounts)]
+pub struct CreateFriendList<'info> {
+    #[account(
+        init,
+        payer = wallet,
+        space = FriendList::space(),
+        seeds = [b"friends", wallet.key().as_ref()],
+        bump
+    )]
+    pub friend_list: Account<'info, FriendList>,
+    
+    #[account(mut)]
+    pub wallet: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(target: Pubkey)]
+pub struct SendFriendRequest<'info> {
+    #[account(mut, seeds = [b"friends", target.as_ref()], bump)]
+    pub target_friend_list: Account<'info, FriendList>,
+    
+    pub sender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(other: Pubkey)]
+pub struct UpdateFriendship<'info> {
+    #[account(
+        mut,
+        seeds = [b"friends", wallet.key().as_ref()],
+        bump,
+        has_one = wallet @ DuelError::Unauthorized
+    )]
+    pub friend_list: Account<'info, FriendList>,
+    
+    #[account(mut, seeds = [b"friends", other.as_ref()], bump)]
+    pub other_friend_list: Account<'info, FriendList>,
+    
+    pub wallet: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DeclineFriendRequest<'info> {
+    #[account(
+        mut,
+        seeds = [b"friends", wallet.key().as_ref()],
+        bump,
+        has_one = wallet @ DuelError::Unauthorized
+    )]
+    pub friend_list: Account<'info, FriendList>,
+    
+    pub wallet: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CreateDuel<'info> {
+    // Seeded per creator so concurrent creations never race for the same address
+    #[account(
+        init,
+        payer = creator,
+        space = Duel::space(),
+        seeds = [b"duel", creator.key().as_ref(), nonce.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    
+    #[account(seeds = [b"profile", creator.key().as_ref()], bump)]
+    pub creator_profile: Option<Account<'info, PlayerProfile>>,
+    
+    #[account(mut, address = protocol.treasury @ DuelError::Unauthorized)]
+    /// CHECK: Treasury account receiving the creation fee
+    pub treasury: UncheckedAccount<'info>,
+    
+    // Counts the creation in the activity heatmap when supplied
+    #[account(mut, seeds = [b"heatmap"], bump)]
+    pub heatmap: Option<Account<'info, DuelHeatmap>>,
+    
+    // Created empty when supplied (otherwise with init_price_index); filled in
+    // later with set_price_index
+    #[account(
+        init,
+        payer = creator,
+        space = DuelPriceIndex::space(),
+        seeds = [b"price_index", duel.key().as_ref()],
+        bump
+    )]
+    pub price_index: Option<Account<'info, DuelPriceIndex>>,
+    
+    // Every later call that modifies the duel appends to it
+    #[account(
+        init,
+        payer = creator,
+        space = DuelAuditLog::space(),
+        seeds = [b"audit", duel.key().as_ref()],
+        bump
+    )]
+    pub audit_log: Account<'info, DuelAuditLog>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u64)]
+pub struct CreateTemplate<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = DuelTemplate::space(),
+        seeds = [b"template", creator.key().as_ref(), template_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub template: Account<'info, DuelTemplate>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDuelFromTemplate<'info> {
+    pub create_duel: CreateDuel<'info>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"template",
+            template.creator.as_ref(),
+            template.template_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = template.creator == create_duel.creator.key() @ DuelError::Unauthorized
+    )]
+    pub template: Account<'info, DuelTemplate>,
+}
+
+#[derive(Accounts)]
+pub struct DeleteTemplate<'info> {
+    #[account(
+        mut,
+        close = creator,
+        seeds = [
+            b"template",
+            template.creator.as_ref(),
+            template.template_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        has_one = creator @ DuelError::Unauthorized
+    )]
+    pub template: Account<'info, DuelTemplate>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(category: DuelCategory)]
+pub struct CreateCategoryLeaderboard<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = CategoryLeaderboard::space(),
+        seeds = [b"cat_leaderboard".as_ref(), &[category as u8]],
+        bump
+    )]
+    pub category_leaderboard: Account<'info, CategoryLeaderboard>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateHeatmap<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = DuelHeatmap::space(),
+        seeds = [b"heatmap"],
+        bump
+    )]
+    pub heatmap: Account<'info, DuelHeatmap>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResetHeatmapBucket<'info> {
+    #[account(mut, seeds = [b"heatmap"], bump)]
+    pub heatmap: Account<'info, DuelHeatmap>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreatePool<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = DuelPool::space(),
+        seeds = [b"pool", protocol.total_pools.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub pool: Account<'info, DuelPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct JoinPool<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, DuelPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"pool_escrow", pool.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account holding the pool prize
+    pub pool_escrow: UncheckedAccount<'info>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    // Required while the protocol requires profiles for participation
+    #[account(seeds = [b"profile", player.key().as_ref()], bump)]
+    pub player_profile: Option<Account<'info, PlayerProfile>>,
+    
+    #[account(mut)]
+    pub player: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LeavePool<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, DuelPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"pool_escrow", pool.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account holding the pool prize
+    pub pool_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub player: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StartPool<'info> {
+    #[account(mut, has_one = creator @ DuelError::Unauthorized)]
+    pub pool: Account<'info, DuelPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordPoolResult<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, DuelPool>,
+    
+    pub duel: Account<'info, Duel>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizePool<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, DuelPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"pool_escrow", pool.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account holding the pool prize
+    pub pool_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    /// CHECK: Highest-scoring participant, verified against the pool scores
+    pub winner: UncheckedAccount<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProtocolHealthCheck<'info> {
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(address = protocol.treasury @ DuelError::Unauthorized)]
+    /// CHECK: Treasury account, only its balance is read
+    pub treasury: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EstimateSettleCompute<'info> {
+    pub duel: Account<'info, Duel>,
+}
+
+#[derive(Accounts)]
+pub struct GetTimeRemaining<'info> {
+    pub duel: Account<'info, Duel>,
+}
+
+#[derive(Accounts)]
+pub struct CreateRankedQueue<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 4 + (53 * MAX_RANKED_QUEUE_ENTRIES),
+        seeds = [b"ranked_queue"],
+        bump
+    )]
+    pub ranked_queue: Account<'info, RankedQueue>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_amount: u64, nonce: u64)]
+pub struct JoinRankedQueue<'info> {
+    #[account(mut, seeds = [b"ranked_queue"], bump)]
+    pub ranked_queue: Account<'info, RankedQueue>,
+    
+    #[account(
+        mut,
+        seeds = [b"queue_escrow"],
+        bump
+    )]
+    /// CHECK: Escrow account for holding queued stakes
+    pub queue_escrow: UncheckedAccount<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        seeds = [b"duel", player.key().as_ref(), nonce.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: Duel account, only created when a match is found
+    pub duel: UncheckedAccount<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"audit", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Duel audit log, only created when a match is found
+    pub audit_log: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub player: Signer<'info>,
+    
+    #[account(seeds = [b"profile", player.key().as_ref()], bump)]
+    pub player_profile: Account<'info, PlayerProfile>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LeaveRankedQueue<'info> {
+    #[account(mut, seeds = [b"ranked_queue"], bump)]
+    pub ranked_queue: Account<'info, RankedQueue>,
+    
+    #[account(
+        mut,
+        seeds = [b"queue_escrow"],
+        bump
+    )]
+    /// CHECK: Escrow account for holding queued stakes
+    pub queue_escrow: UncheckedAccount<'info>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub player: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptDuel<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub opponent: Signer<'info>,
+    
+    // Both profiles are required while ranked mode is enabled
+    #[account(seeds = [b"profile", duel.creator.as_ref()], bump)]
+    pub creator_profile: Option<Account<'info, PlayerProfile>>,
+    
+    #[account(seeds = [b"profile", opponent.key().as_ref()], bump)]
+    pub opponent_profile: Option<Account<'info, PlayerProfile>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct DepositStake<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    
+    // Required for duels created with `use_wsol`
+    #[account(
+        mut,
+        seeds = [b"wsol_escrow", duel.key().as_ref()],
+        bump
+    )]
+    pub wsol_escrow: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Option<Program<'info, Token>>,
+    
+    #[account(mut)]
+    /// CHECK: The other participant, refunded if this deposit exceeds the
+    /// protocol's maximum deposit gap; verified in the handler
+    pub counterparty: Option<UncheckedAccount<'info>>,
+    
+    // Required for the deposit that fully funds the duel
+    #[account(
+        mut,
+        seeds = [b"timer", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: DuelTimer PDA, allocated in the handler
+    pub duel_timer: Option<UncheckedAccount<'info>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct CreateWsolEscrow<'info> {
+    pub duel: Account<'info, Duel>,
+    
+    #[account(seeds = [b"escrow", duel.key().as_ref()], bump)]
+    /// CHECK: SOL escrow PDA, used as the token escrow's authority
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"wsol_escrow", duel.key().as_ref()],
+        bump,
+        token::mint = native_mint,
+        token::authority = duel_escrow
+    )]
+    pub wsol_escrow: Account<'info, TokenAccount>,
+    
+    #[account(address = token::spl_token::native_mint::ID)]
+    pub native_mint: Account<'info, Mint>,
+    
+    #[account(mut, address = duel.creator @ DuelError::Unauthorized)]
+    pub creator: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockDuel<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    // Anyone may unlock the duel; recorded in the audit log
+    pub caller: Signer<'info>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeOracleTracking<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(constraint = protocol.authorized_oracles.contains(&oracle.key()) @ DuelError::UnauthorizedOracle)]
+    pub oracle: Signer<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"timer", duel.key().as_ref()],
+        bump
+    )]
+    pub duel_timer: Option<Account<'info, DuelTimer>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireOracleInit<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.creator @ DuelError::NotParticipant)]
+    /// CHECK: Creator account to receive a refund
+    pub creator: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.opponent @ DuelError::NotParticipant)]
+    /// CHECK: Opponent account to receive a refund
+    pub opponent: UncheckedAccount<'info>,
+    
+    // Anyone may expire the duel once the deadline passes; recorded in the audit log
+    pub caller: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    
+    // Required for duels created with `use_wsol`
+    #[account(
+        mut,
+        seeds = [b"wsol_escrow", duel.key().as_ref()],
+        bump
+    )]
+    pub wsol_escrow: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Option<Program<'info, Token>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct PauseDuel<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    pub participant: Signer<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"timer", duel.key().as_ref()],
+        bump
+    )]
+    pub duel_timer: Option<Account<'info, DuelTimer>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct IssueDuelNotice<'info> {
+    #[account(
+        mut,
+        constraint = !duel.warning_notice_present @ DuelError::NoticeAlreadyIssued
+    )]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 32 + DUEL_NOTICE_MAX_LEN + 32 + 8,
+        seeds = [b"notice", duel.key().as_ref()],
+        bump
+    )]
+    pub notice: Account<'info, DuelNotice>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDuelForum<'info> {
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        init,
+        payer = payer,
+        space = DuelForum::space(),
+        seeds = [b"forum", duel.key().as_ref()],
+        bump
+    )]
+    pub forum: Account<'info, DuelForum>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PostForumComment<'info> {
+    #[account(
+        mut,
+        seeds = [b"forum", forum.duel.as_ref()],
+        bump
+    )]
+    pub forum: Account<'info, DuelForum>,
+    
+    pub author: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ModerateForum<'info> {
+    #[account(
+        mut,
+        seeds = [b"forum", forum.duel.as_ref()],
+        bump
+    )]
+    pub forum: Account<'info, DuelForum>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[cfg(feature = "devnet")]
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CreateTestDuel<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = Duel::space(),
+        seeds = [b"duel", creator.key().as_ref(), nonce.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    
+    /// CHECK: Any wallet; test duels do not need the opponent to sign
+    pub opponent: UncheckedAccount<'info>,
+    
+    #[account(
+        init,
+        payer = creator,
+        space = DuelAuditLog::space(),
+        seeds = [b"audit", duel.key().as_ref()],
+        bump
+    )]
+    pub audit_log: Account<'info, DuelAuditLog>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddAllowedToken<'info> {
+    #[account(
+        mut,
+        has_one = creator @ DuelError::Unauthorized,
+        realloc = Duel::space()
+            + 32 * (duel.allowed_tokens.len() + 1).saturating_sub(DUEL_RESERVED_ALLOWED_TOKENS),
+        realloc::payer = creator,
+        realloc::zero = false
+    )]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct InitPriceIndex<'info> {
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = DuelPriceIndex::space(),
+        seeds = [b"price_index", duel.key().as_ref()],
+        bump
+    )]
+    pub price_index: Account<'info, DuelPriceIndex>,
+    
+    // The duel's creator or an authorized oracle
+    #[account(
+        mut,
+        constraint = can_edit_price_index(&duel, &protocol, &authority.key()) @ DuelError::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceIndex<'info> {
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        seeds = [b"price_index", duel.key().as_ref()],
+        bump
+    )]
+    pub price_index: Account<'info, DuelPriceIndex>,
+    
+    // The duel's creator or an authorized oracle
+    #[account(constraint = can_edit_price_index(&duel, &protocol, &authority.key()) @ DuelError::Unauthorized)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePositions<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        constraint = protocol.authorized_oracles.contains(&oracle.key()) @ DuelError::UnauthorizedOracle
+    )]
+    pub oracle: Signer<'info>,
+    
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: Instructions sysvar, read to find the trade proof's Ed25519 verification
+    pub instructions_sysvar: UncheckedAccount<'info>,
+    
+    // Settlement accounts, only required when a FirstToTarget duel may settle early
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: Option<UncheckedAccount<'info>>,
+    
+    #[account(mut, address = duel.creator @ DuelError::NotParticipant)]
+    /// CHECK: Creator account to receive winnings
+    pub creator: Option<UncheckedAccount<'info>>,
+    
+    #[account(mut, address = duel.opponent @ DuelError::NotParticipant)]
+    /// CHECK: Opponent account to receive winnings
+    pub opponent: Option<UncheckedAccount<'info>>,
+    
+    #[account(mut, address = protocol.treasury @ DuelError::Unauthorized)]
+    /// CHECK: Treasury account for fees
+    pub treasury: Option<UncheckedAccount<'info>>,
+    
+    pub system_program: Option<Program<'info, System>>,
+    
+    #[account(
+        init,
+        payer = oracle,
+        space = DuelFee::space(),
+        seeds = [b"fee", duel.key().as_ref()],
+        bump
+    )]
+    pub duel_fee: Option<Account<'info, DuelFee>>,
+    
+    #[account(
+        init,
+        payer = oracle,
+        space = DuelInsight::space(),
+        seeds = [b"insight", duel.key().as_ref()],
+        bump
+    )]
+    pub duel_insight: Option<Account<'info, DuelInsight>>,
+    
+    #[account(mut, seeds = [b"profile", duel.creator.as_ref()], bump)]
+    pub creator_profile: Option<Account<'info, PlayerProfile>>,
+    
+    #[account(mut, seeds = [b"profile", duel.opponent.as_ref()], bump)]
+    pub opponent_profile: Option<Account<'info, PlayerProfile>>,
+
+    #[account(
+        mut,
+        seeds = [b"wsol_escrow", duel.key().as_ref()],
+        bump
+    )]
+    pub wsol_escrow: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Option<Program<'info, Token>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct SettleDuel<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.creator @ DuelError::NotParticipant)]
+    /// CHECK: Creator account to receive winnings
+    pub creator: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.opponent @ DuelError::NotParticipant)]
+    /// CHECK: Opponent account to receive winnings
+    pub opponent: UncheckedAccount<'info>,
+    
+    #[account(mut, address = protocol.treasury @ DuelError::WrongTreasury)]
+    /// CHECK: Treasury account for fees
+    pub treasury: UncheckedAccount<'info>,
+    
+    #[account(
+        init,
+        payer = settler,
+        space = DuelFee::space(),
+        seeds = [b"fee", duel.key().as_ref()],
+        bump
+    )]
+    pub duel_fee: Account<'info, DuelFee>,
+    
+    #[account(
+        init,
+        payer = settler,
+        space = DuelInsight::space(),
+        seeds = [b"insight", duel.key().as_ref()],
+        bump
+    )]
+    pub duel_insight: Account<'info, DuelInsight>,
+    
+    #[account(
+        mut,
+        seeds = [b"cat_leaderboard".as_ref(), &[duel.category as u8]],
+        bump
+    )]
+    pub category_leaderboard: Option<Account<'info, CategoryLeaderboard>>,
+    
+    // Supplies the winner's current ELO for the category leaderboard
+    pub winner_profile: Option<Account<'info, PlayerProfile>>,
+    
+    // Participants' profiles: their settled-duel counts are incremented
+    #[account(mut, seeds = [b"profile", duel.creator.as_ref()], bump)]
+    pub creator_profile: Option<Account<'info, PlayerProfile>>,
+    
+    #[account(mut, seeds = [b"profile", duel.opponent.as_ref()], bump)]
+    pub opponent_profile: Option<Account<'info, PlayerProfile>>,
+    
+    // Counts the settlement in the activity heatmap when supplied
+    #[account(mut, seeds = [b"heatmap"], bump)]
+    pub heatmap: Option<Account<'info, DuelHeatmap>>,
+    
+    #[account(mut)]
+    pub settler: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    
+    // Required for duels created with `use_wsol`
+    #[account(
+        mut,
+        seeds = [b"wsol_escrow", duel.key().as_ref()],
+        bump
+    )]
+    pub wsol_escrow: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Option<Program<'info, Token>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct SettleAndCloseDuel<'info> {
+    pub settle: SettleDuel<'info>,
+    
+    // Closed along with the duel when the creator paid for it; otherwise
+    // close it separately with close_duel_timer
+    #[account(
+        mut,
+        seeds = [b"timer", settle.duel.key().as_ref()],
+        bump,
+        constraint = duel_timer.rent_payer == settle.duel.creator @ DuelError::Unauthorized
+    )]
+    pub duel_timer: Option<Account<'info, DuelTimer>>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyCancelDuel<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        has_one = authority @ DuelError::Unauthorized,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.creator @ DuelError::NotParticipant)]
+    /// CHECK: Creator account to receive a refund
+    pub creator: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.opponent @ DuelError::NotParticipant)]
+    /// CHECK: Opponent account to receive a refund, required once the opponent has deposited
+    pub opponent: Option<UncheckedAccount<'info>>,
+    
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    
+    // Required for duels created with `use_wsol`
+    #[account(
+        mut,
+        seeds = [b"wsol_escrow", duel.key().as_ref()],
+        bump
+    )]
+    pub wsol_escrow: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Option<Program<'info, Token>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct CloseDuelFee<'info> {
+    #[account(
+        mut,
+        close = creator,
+        seeds = [b"fee", duel_fee.duel.as_ref()],
+        bump,
+        has_one = creator @ DuelError::Unauthorized
+    )]
+    pub duel_fee: Account<'info, DuelFee>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseDuelInsight<'info> {
+    #[account(
+        mut,
+        close = creator,
+        seeds = [b"insight", duel_insight.duel.as_ref()],
+        bump,
+        has_one = creator @ DuelError::Unauthorized
+    )]
+    pub duel_insight: Account<'info, DuelInsight>,
+    
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseDuelTimer<'info> {
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        close = rent_payer,
+        seeds = [b"timer", duel.key().as_ref()],
+        bump,
+        has_one = rent_payer @ DuelError::Unauthorized
+    )]
+    pub duel_timer: Account<'info, DuelTimer>,
+    
+    #[account(mut)]
+    /// CHECK: Receives the timer's rent; must match the timer's payer
+    pub rent_payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct BulkSettle<'info> {
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    pub settler: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelDuel<'info> {
+    #[account(mut)]
+    pub duel: Account<'info, Duel>,
+    
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump,
+        constraint = protocol.initialized @ DuelError::ProtocolNotInitialized
+    )]
+    pub protocol: Account<'info, Protocol>,
+    
+    #[account(
+        mut,
+        seeds = [b"escrow", duel.key().as_ref()],
+        bump
+    )]
+    /// CHECK: Escrow account for holding stakes
+    pub duel_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.creator @ DuelError::NotParticipant)]
+    /// CHECK: Creator account to receive a refund
+    pub creator: UncheckedAccount<'info>,
+    
+    #[account(mut, address = duel.opponent @ DuelError::NotParticipant)]
+    /// CHECK: Opponent account to receive a refund, required once the opponent has deposited
+    pub opponent: Option<UncheckedAccount<'info>>,
+    
+    pub canceller: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+    
+    // Required for duels created with `use_wsol` once a stake is deposited
+    #[account(
+        mut,
+        seeds = [b"wsol_escrow", duel.key().as_ref()],
+        bump
+    )]
+    pub wsol_escrow: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Option<Program<'info, Token>>,
+    
+    // Every call is recorded in the duel's audit log
+    #[account(mut, seeds = [b"audit", duel.key().as_ref()], bump)]
+    pub audit_log: Account<'info, DuelAuditLog>,
+}
+
+// Events
+#[event]
+pub struct PositionUpdate {
+    pub duel: Pubkey,
+    pub creator_value: u64,
+    pub opponent_value: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OracleUpdateRecorded {
+    pub duel: Pubkey,
+    pub oracle: Pubkey,
+    pub update_count: u32,
+}
+
+#[event]
+pub struct ClientVersionRejected {
+    pub client_version: u32,
+    pub min_required: u32,
+}
+
+#[event]
+pub struct MatchFound {
+    pub player1: Pubkey,
+    pub player2: Pubkey,
+    pub duel: Pubkey,
+}
+
+#[event]
+pub struct EmergencyDuelCancelled {
+    pub duel: Pubkey,
+    pub cancelled_by: Pubkey,
+    pub reason_hash: [u8; 32],
+}
+
+#[event]
+pub struct ComputeUsed {
+    pub instruction_name: [u8; 32],
+    pub compute_units: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DuelSettled {
+    pub duel: Pubkey,
+    pub winner: DuelWinner,
+    pub creator_pnl: i64,
+    pub opponent_pnl: i64,
+    pub winner_payout: u64,
+    pub protocol_fee: u64,
+}
+
+#[event]
+pub struct WhitepaperUpdated {
+    pub old_hash: [u8; 32],
+    pub new_hash: [u8; 32],
+    pub updated_by: Pubkey,
+}
+
+#[event]
+pub struct TermsUpdated {
+    pub old_hash: [u8; 32],
+    pub new_hash: [u8; 32],
+    pub updated_by: Pubkey,
+}
+
+#[event]
+pub struct FriendAdded {
+    pub wallet: Pubkey,
+    pub friend: Pubkey,
+}
+
+#[event]
+pub struct EarlySettlement {
+    pub duel: Pubkey,
+    pub winner: DuelWinner,
+    pub triggering_pnl: i64,
+}
+
+#[event]
+pub struct ForceSettlementUsed {
+    pub duel: Pubkey,
+    pub forced_winner: DuelWinner,
+    pub authority: Pubkey,
+}
+
+#[event]
+pub struct CategoryLeaderboardUpdated {
+    pub category: DuelCategory,
+    pub player: Pubkey,
+    pub new_rank: u8, // 1-based
+}
+
+#[event]
+pub struct CreationFeePaid {
+    pub duel: Pubkey,
+    pub creator: Pubkey,
+    pub fee_amount: u64,
+}
+
+#[event]
+pub struct PoolFinalized {
+    pub pool: Pubkey,
+    pub winner: Pubkey,
+    pub score: u32,
+    pub prize: u64,
+}
+
+#[event]
+pub struct ProtocolHealth {
+    pub treasury_balance: u64,
     pub total_duels: u64,
     pub total_volume: u64,
+    pub total_active_stake: u64, // Stake escrowed in funded duels
+    pub pending_duels: u64,
+    pub accepted_duels: u64,
+    pub paused_instructions: u16, // Non-zero when any instruction is paused
+    pub version: u32,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TreasuryChangePending {
+    pub old_treasury: Pubkey,
+    pub new_treasury: Pubkey,
+    pub unlocks_at: i64,
+}
+
+#[event]
+pub struct DuelAutoCancelled {
+    pub duel: Pubkey,
+    pub deposit_gap: i64,
+}
+
+#[event]
+pub struct ActiveStakeUpdated {
+    pub new_total: u64,
+    pub delta: i64,
+}
+
+#[event]
+pub struct ReputationScore {
+    pub player: Pubkey,
+    pub score: u64,
+    pub elo: u32,
+    pub win_streak: u32,
+    pub total_volume: u64,
+}
+
+#[event]
+pub struct ReputationUpdated {
+    pub player: Pubkey,
+    pub old_score: u64,
+    pub new_score: u64,
+}
+
+#[event]
+pub struct ComputeEstimate {
+    pub duel: Pubkey,
+    pub estimated_cus: u64,
+    pub is_draw: bool,
+}
+
+#[event]
+pub struct TimeRemaining {
+    pub duel: Pubkey,
+    pub end_time: Option<i64>,
+    pub remaining_seconds: i64,
+    pub status: DuelStatus,
 }
 
-#[account]
-pub struct Duel {
+#[event]
+pub struct LoyaltyDiscountApplied {
+    pub duel: Pubkey,
+    pub player: Pubkey,
+    pub effective_fee_bps: u16,
+}
+
+#[event]
+pub struct DuelClosed {
+    pub duel: Pubkey,
+    pub rent_returned: u64,
+}
+
+#[event]
+pub struct DuelCreatedFromTemplate {
+    pub template: Pubkey,
+    pub duel: Pubkey,
+}
+
+// Everything an indexer needs to list a new duel without reading the account
+#[event]
+pub struct DuelCreated {
+    pub duel: Pubkey,
+    pub duel_id: u64, // The creator's PDA nonce
     pub creator: Pubkey,
-    pub opponent: Pubkey,
     pub stake_amount: u64,
-    pub created_at: i64,
-    pub start_time: i64,
-    pub end_time: i64,
     pub duration: i64,
-    pub status: DuelStatus,
-    pub winner: DuelWinner,
-    pub creator_stake_deposited: bool,
-    pub opponent_stake_deposited: bool,
     pub allowed_tokens: Vec<Pubkey>,
-    pub creator_starting_value: u64,
-    pub opponent_starting_value: u64,
-    pub creator_final_value: u64,
-    pub opponent_final_value: u64,
+    pub category: DuelCategory,
+    pub invited_opponent: Option<Pubkey>,
+    pub created_at: i64,
+    pub creator_alias: [u8; 32],
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
-pub enum DuelStatus {
-    Pending,
-    Accepted,
-    Active,
-    Settled,
-    Cancelled,
+#[event]
+pub struct DuelAccepted {
+    pub duel: Pubkey,
+    pub opponent: Pubkey,
+    pub opponent_alias: [u8; 32],
+    pub accepted_at: i64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
-pub enum DuelWinner {
-    None,
-    Creator,
-    Opponent,
-    Draw,
+#[event]
+pub struct FeeProposalCreated {
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+    pub proposed_fee_bps: u16,
+    pub voting_ends_at: i64,
 }
 
-// Context structs
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + 32 + 32 + 2 + 8 + 8,
-        seeds = [b"protocol"],
-        bump
-    )]
-    pub protocol: Account<'info, Protocol>,
+#[event]
+pub struct FeeProposalExecuted {
+    pub proposal: Pubkey,
+    pub old_fee_bps: u16,
+    pub new_fee_bps: u16,
+}
+
+#[event]
+pub struct UpdateCountReached {
+    pub duel: Pubkey,
+    pub count: u32,
+    pub required: u8,
+}
+
+#[event]
+pub struct WinConditionOverride {
+    pub duel: Pubkey,
+    pub original: WinCondition,
+    pub applied: WinCondition,
+}
+
+#[event]
+pub struct ProtocolClosed {
+    pub authority: Pubkey,
+    pub closed_at: i64,
+    pub total_volume_lifetime: u64,
+}
+
+#[event]
+pub struct ParticipationRequirementChanged {
+    pub required: bool,
+}
+
+// Error codes
+#[error_code]
+pub enum DuelError {
+    #[msg("Invalid duel status for this operation")]
+    InvalidStatus,
+    #[msg("Duel has already been accepted")]
+    DuelAlreadyAccepted,
+    #[msg("Not a participant in this duel")]
+    NotParticipant,
+    #[msg("Duel has expired")]
+    DuelExpired,
+    #[msg("Duel has not expired yet")]
+    DuelNotExpired,
+    #[msg("Cannot cancel duel in current status")]
+    CannotCancel,
+    #[msg("Cannot cancel a duel once trading has started")]
+    CannotCancelActive,
+    #[msg("Duel is already settled or cancelled")]
+    CannotCancelSettled,
+    #[msg("Only the creator can cancel a pending duel")]
+    CannotCancelOtherCreator,
+    #[msg("Trade proof is missing or does not match its Ed25519 verification")]
+    InvalidTradeProof,
+    #[msg("Unauthorized action")]
+    Unauthorized,
+    #[msg("Duration too short for protocol minimum")]
+    DurationTooShort,
+    #[msg("Duration too long for protocol maximum")]
+    DurationTooLong,
+    #[msg("Invalid duel duration bounds")]
+    InvalidDurationBounds,
+    #[msg("This instruction is currently paused")]
+    InstructionPaused,
+    #[msg("Instruction index out of range")]
+    InvalidInstructionIndex,
+    #[msg("Duel stakes are still locked")]
+    DuelLocked,
+    #[msg("Lock period cannot be negative")]
+    InvalidLockPeriod,
+    #[msg("Too many duels in a single bulk settlement")]
+    BulkSettleTooMany,
+    #[msg("Bulk settlement accounts do not match the duel list")]
+    InvalidBulkAccounts,
+    #[msg("Duel is paused by its participants")]
+    DuelPaused,
+    #[msg("Opponent account is required to refund their stake")]
+    OpponentAccountRequired,
+    #[msg("Duel category is disabled")]
+    CategoryDisabled,
+    #[msg("Too many distinct oracles have updated this duel")]
+    TooManyDistinctOracles,
+    #[msg("Client version is too old, please upgrade")]
+    ClientVersionTooOld,
+    #[msg("Display name cannot exceed 32 bytes")]
+    DisplayNameTooLong,
+    #[msg("Stake is below the escrow rent-exempt minimum")]
+    StakeBelowRentExempt,
+    #[msg("A notice has already been issued for this duel")]
+    NoticeAlreadyIssued,
+    #[msg("Notice content is too long")]
+    NoticeTooLong,
+    #[msg("No allowed tokens given and no protocol defaults set")]
+    NoDefaultTokensSet,
+    #[msg("Too many default allowed tokens")]
+    TooManyDefaultTokens,
+    #[msg("Stake is too low for the players' rank")]
+    StakeTooLowForRank,
+    #[msg("Settlement record is still within its retention period")]
+    RecordRetentionActive,
+    #[msg("Player is already in the ranked queue")]
+    AlreadyInQueue,
+    #[msg("Player is not in the ranked queue")]
+    NotInQueue,
+    #[msg("Ranked queue is full")]
+    QueueFull,
+    #[msg("Queue timeout has not been reached yet")]
+    QueueTimeoutNotReached,
+    #[msg("Queue timeout cannot be negative")]
+    InvalidQueueTimeout,
+    #[msg("Token allocation exceeds the duel's weight limit")]
+    AllocationLimitExceeded,
+    #[msg("Too many token allocation limits")]
+    TooManyAllocationLimits,
+    #[msg("Allocation limit cannot exceed 10000 bps")]
+    InvalidAllocationLimit,
+    #[msg("Protocol has not been initialized")]
+    ProtocolNotInitialized,
+    #[msg("Protocol fee is too small to fund the creator bonus")]
+    InsufficientTreasuryForBonus,
+    #[msg("Bonus cannot exceed twice the protocol fee")]
+    InvalidBonusBps,
+    #[msg("Account balance does not match the expected amount after transfer")]
+    BalanceMismatch,
+    #[msg("Terms hash does not match the currently published terms")]
+    TermsHashMismatch,
+    #[msg("Clock timestamp is outside the accepted range")]
+    InvalidTimestamp,
+    #[msg("Friend list is full")]
+    FriendListFull,
+    #[msg("Cannot send a friend request to yourself")]
+    CannotFriendSelf,
+    #[msg("Players are already friends")]
+    AlreadyFriends,
+    #[msg("Friend request already sent")]
+    FriendRequestAlreadySent,
+    #[msg("Friend request not found")]
+    FriendRequestNotFound,
+    #[msg("Players are not friends")]
+    NotFriends,
+    #[msg("Target PnL must be positive")]
+    InvalidWinCondition,
+    #[msg("Settlement accounts are required to settle this duel early")]
+    SettlementAccountsRequired,
+    #[msg("Forced winner must be Creator, Opponent or Draw")]
+    InvalidForcedWinner,
+    #[msg("Position value moved too far since the last update")]
+    UpdateValueTooExtreme,
+    #[msg("Insufficient funds to pay the duel creation fee")]
+    InsufficientFundsForCreationFee,
+    #[msg("Failed to sync the wrapped SOL escrow")]
+    WrapSolFailed,
+    #[msg("WSOL escrow and token program accounts are required for this duel")]
+    WsolEscrowRequired,
+    #[msg("Duel does not use a WSOL escrow")]
+    WsolNotEnabled,
+    #[msg("Pool has already started")]
+    PoolAlreadyStarted,
+    #[msg("Pool must have between 2 and 8 participants")]
+    InvalidPoolSize,
+    #[msg("Pool is full")]
+    PoolFull,
+    #[msg("Player has already joined this pool")]
+    AlreadyInPool,
+    #[msg("Pool needs all participants before it can start")]
+    PoolNotFull,
+    #[msg("Pool has not started")]
+    PoolNotStarted,
+    #[msg("Pool has already been finalized")]
+    PoolAlreadyFinalized,
+    #[msg("Invalid accounts passed for pool duels")]
+    InvalidPoolAccounts,
+    #[msg("Duel does not belong to this pool")]
+    DuelNotInPool,
+    #[msg("Pool result already recorded for this duel")]
+    PoolResultAlreadyRecorded,
+    #[msg("Not all pool duel results have been recorded")]
+    PoolResultsPending,
+    #[msg("Winner account does not match the pool's top scorer")]
+    PoolWinnerMismatch,
+    #[msg("Refund calculation underflowed — fee too high")]
+    EscrowUnderflow,
+    #[msg("Arithmetic overflow")]
+    Overflow,
+    #[msg("Treasury change time-lock has not expired")]
+    TimeLockNotExpired,
+    #[msg("No treasury change is pending")]
+    NoPendingTreasuryChange,
+    #[msg("Invalid treasury address")]
+    InvalidTreasury,
+    #[msg("Maximum deposit gap cannot be negative")]
+    InvalidDepositGap,
+    #[msg("Counterparty account is required to refund a late deposit")]
+    CounterpartyAccountRequired,
+    #[msg("Oracle initialization deadline must be positive")]
+    InvalidOracleInitDeadline,
+    #[msg("Oracle initialization deadline has passed")]
+    OracleInitDeadlinePassed,
+    #[msg("Oracle initialization deadline has not passed yet")]
+    OracleInitDeadlineNotReached,
+    #[msg("Starting portfolio values must be positive")]
+    InvalidStartingValue,
+    #[msg("Only manually expiring duels accept a final update")]
+    InvalidExpiryMode,
+    #[msg("Manual duel has not been finalized by the oracle")]
+    CannotSettleManualMode,
+    #[msg("Duel timer account is required for the final deposit")]
+    DuelTimerRequired,
+    #[msg("Native SOL mint is only allowed in duels that use SOL natively")]
+    NativeMintInAllowedTokens,
+    #[msg("Comment must be between 1 and 280 bytes")]
+    InvalidCommentLength,
+    #[msg("Duel forum is full")]
+    ForumFull,
+    #[msg("Comment not found")]
+    CommentNotFound,
+    #[msg("Escrow account is not the duel's escrow PDA")]
+    EscrowNotPDA,
+    #[msg("Escrow account has an unexpected owner")]
+    InvalidEscrowOwner,
+    #[msg("Too many allowed tokens")]
+    TooManyAllowedTokens,
+    #[msg("Duel has no accepted opponent yet")]
+    OpponentNotSet,
+    #[msg("Duel account is in an inconsistent state")]
+    InvalidDuelState,
+    #[msg("Loyalty discount cannot exceed 10000 bps")]
+    InvalidLoyaltyDiscount,
+    #[msg("Reputation volume divisor must be positive")]
+    InvalidReputationWeights,
+    #[msg("Oracle signature over the reported values is missing or invalid")]
+    InvalidOracleSignature,
+    #[msg("PnL calculation overflowed")]
+    PnlCalculationFailed,
+    #[msg("Protocol duel counter overflowed")]
+    TotalDuelsOverflow,
+    #[msg("Protocol volume overflowed")]
+    VolumeOverflow,
+    #[msg("Heatmap bucket index out of range")]
+    InvalidHeatmapBucket,
+    #[msg("Treasury account does not match the protocol treasury")]
+    WrongTreasury,
+    #[msg("Fee cannot exceed 10000 bps")]
+    InvalidFeeBps,
+    #[msg("Fee proposal has already been executed")]
+    ProposalAlreadyExecuted,
+    #[msg("Voting on this proposal has closed")]
+    VotingClosed,
+    #[msg("Voting on this proposal is still open")]
+    VotingStillOpen,
+    #[msg("Fee proposal did not pass")]
+    ProposalRejected,
+    #[msg("Duel is already being settled or funded")]
+    ReentrancyDetected,
+    #[msg("System program account is not the System Program")]
+    InvalidSystemProgram,
+    #[msg("Token program account is not the SPL Token program")]
+    InvalidTokenProgram,
+    #[msg("Position update is earlier than the duel's last update")]
+    OracleTimestampBackwards,
+    #[msg("Price feed token is not in the duel's allowed tokens")]
+    FeedNotForAllowedToken,
+    #[msg("Too many price feeds")]
+    TooManyPriceFeeds,
+    #[msg("Token already has a price feed")]
+    DuplicatePriceFeed,
+    #[msg("Protocol still has funded duels")]
+    ActiveDuelsExist,
+    #[msg("Token is already in the duel's allowed list")]
+    TokenAlreadyInList,
+    #[msg("Trade proof token is not in the duel's allowed list")]
+    TokenNotInAllowedList,
+    #[msg("Trade proof is timestamped before the duel started")]
+    TradeBeforeDuelStart,
+    #[msg("Duel has not received enough oracle updates to settle")]
+    InsufficientOracleUpdates,
+    #[msg("Account data length does not match a Duel account")]
+    AccountDataLengthMismatch,
+    #[msg("Account is not a Duel account")]
+    WrongAccountType,
+    #[msg("A player profile is required to participate")]
+    ProfileRequired,
+    #[msg("Signer is not an authorized oracle")]
+    UnauthorizedOracle,
+    #[msg("Too many authorized oracles")]
+    TooManyAuthorizedOracles,
+    #[msg("Trade proof is not signed by a registered trade attestor")]
+    UnknownTradeAttestor,
+    #[msg("Too many trade attestors")]
+    TooManyTradeAttestors,
+    #[msg("Audit log account does not match the duel")]
+    InvalidAuditLog,
+    #[msg("Ranked queue still has queued players")]
+    RankedQueueNotEmpty,
+    #[msg("Category leaderboard account does not match its category")]
+    InvalidLeaderboardAccount,
+    #[msg("Player is not in this pool")]
+    NotInPool,
+}
+
+// Helper functions
+// SHA-256 of an instruction's borsh-serialized arguments, for the audit log
+fn audit_args_hash<T: AnchorSerialize>(args: &T) -> Result<[u8; 32]> {
+    Ok(hashv(&[&borsh::to_vec(args)?]).to_bytes())
+}
+
+// Append an entry to a duel's audit log, overwriting the oldest once it is full
+fn record_audit(
+    audit_log: &mut DuelAuditLog,
+    instruction_name: &str,
+    signer: Pubkey,
+    instruction_args_hash: [u8; 32],
+) -> Result<()> {
+    let entry = AuditEntry {
+        instruction_name: to_instruction_name(instruction_name),
+        signer,
+        timestamp: Clock::get()?.unix_timestamp,
+        instruction_args_hash,
+    };
+    if audit_log.entries.len() < MAX_AUDIT_ENTRIES {
+        audit_log.entries.push(entry);
+    } else {
+        let next_index = audit_log.next_index as usize;
+        audit_log.entries[next_index] = entry;
+        audit_log.next_index = ((next_index + 1) % MAX_AUDIT_ENTRIES) as u16;
+    }
+    audit_log.total_entries = audit_log.total_entries.saturating_add(1);
+    Ok(())
+}
+
+fn to_instruction_name(name: &str) -> [u8; 32] {
+    let mut instruction_name = [0u8; 32];
+    let len = name.len().min(32);
+    instruction_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    instruction_name
+}
+
+fn to_display_name(bytes: &[u8]) -> Result<[u8; 32]> {
+    require!(bytes.len() <= 32, DuelError::DisplayNameTooLong);
+    
+    let mut display_name = [0u8; 32];
+    display_name[..bytes.len()].copy_from_slice(bytes);
+    Ok(display_name)
+}
+
+fn check_client_version(protocol: &Protocol, client_version: u32) -> Result<()> {
+    if client_version < protocol.min_client_version {
+        emit!(ClientVersionRejected {
+            client_version,
+            min_required: protocol.min_client_version,
+        });
+        return Err(DuelError::ClientVersionTooOld.into());
+    }
+    Ok(())
+}
+
+// Close a program-owned account that was not loaded as an Anchor account,
+// returning its rent to `destination`
+// Rent returned by closing every account in `accounts`
+fn closed_rent(accounts: &[AccountInfo]) -> Result<u64> {
+    accounts.iter().try_fold(0u64, |total, account| {
+        total.checked_add(account.lamports()).ok_or(DuelError::Overflow.into())
+    })
+}
+
+fn close_program_account<'info>(
+    account: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    let new_balance = destination
+        .lamports()
+        .checked_add(account.lamports())
+        .ok_or(DuelError::Overflow)?;
+    **destination.try_borrow_mut_lamports()? = new_balance;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.assign(&anchor_lang::system_program::ID);
+    account.resize(0)?;
+    Ok(())
+}
+
+// Transfer lamports out of a duel escrow PDA
+fn transfer_from_escrow<'info>(
+    system_program: &AccountInfo<'info>,
+    duel_escrow: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    #[cfg(feature = "strict_balance_checks")]
+    let expected_escrow_balance = duel_escrow.lamports() - amount;
+    anchor_lang::system_program::transfer(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Transfer {
+                from: duel_escrow.clone(),
+                to: to.clone(),
+            },
+            signer_seeds,
+        ),
+        amount,
+    )?;
+    #[cfg(feature = "strict_balance_checks")]
+    assert_balance(duel_escrow, expected_escrow_balance)?;
+    Ok(())
+}
+
+// Catch silent payout arithmetic errors by checking the balance a transfer left behind
+#[cfg(feature = "strict_balance_checks")]
+fn assert_balance(account: &AccountInfo, expected: u64) -> Result<()> {
+    let actual = account.lamports();
+    if actual != expected {
+        msg!("Balance mismatch: expected {} lamports, found {}", expected, actual);
+        return err!(DuelError::BalanceMismatch);
+    }
+    Ok(())
+}
+
+// Add a win to the player's leaderboard entry and re-rank by ELO. Players who
+// fall outside the top CATEGORY_LEADERBOARD_SIZE are dropped.
+fn record_category_win(leaderboard: &mut CategoryLeaderboard, player: Pubkey, elo: Option<u32>) {
+    match leaderboard.entries.iter_mut().find(|entry| entry.player == player) {
+        Some(entry) => {
+            entry.wins += 1;
+            if let Some(elo) = elo {
+                entry.elo = elo;
+            }
+        }
+        None => leaderboard.entries.push(CategoryLeaderboardEntry {
+            player,
+            elo: elo.unwrap_or(DEFAULT_ELO_RATING),
+            wins: 1,
+        }),
+    }
+    
+    leaderboard.entries.sort_by(|a, b| b.elo.cmp(&a.elo).then(b.wins.cmp(&a.wins)));
+    leaderboard.entries.truncate(CATEGORY_LEADERBOARD_SIZE);
+    
+    if let Some(idx) = leaderboard.entries.iter().position(|entry| entry.player == player) {
+        emit!(CategoryLeaderboardUpdated {
+            category: leaderboard.category,
+            player,
+            new_rank: idx as u8 + 1,
+        });
+    }
+}
+
+// Check that the instruction just before this one is an Ed25519 program
+// verification of `trade_proof` over (duel, token, trade_amount, timestamp)
+fn verify_trade_proof(
+    instructions_sysvar: &AccountInfo,
+    duel_key: &Pubkey,
+    trade_proof: &TradeProof,
+) -> Result<()> {
+    let mut message = Vec::with_capacity(80);
+    message.extend_from_slice(duel_key.as_ref());
+    message.extend_from_slice(trade_proof.token.as_ref());
+    message.extend_from_slice(&trade_proof.trade_amount.to_le_bytes());
+    message.extend_from_slice(&trade_proof.timestamp.to_le_bytes());
+    
+    verify_ed25519_instruction(
+        instructions_sysvar,
+        1,
+        &trade_proof.trade_signature,
+        &trade_proof.trade_pubkey,
+        &message,
+    )
+    .map_err(|_| error!(DuelError::InvalidTradeProof))
+}
+
+// Check that the instruction two before this one (ahead of the trade proof)
+// is an Ed25519 program verification of the oracle's signature over
+// sha256(duel || creator_value || opponent_value || timestamp)
+fn verify_oracle_signature(
+    instructions_sysvar: &AccountInfo,
+    duel_key: &Pubkey,
+    oracle: &Pubkey,
+    creator_value: u64,
+    opponent_value: u64,
+    timestamp: i64,
+    signature: &[u8; 64],
+) -> Result<()> {
+    let message = hashv(&[
+        duel_key.as_ref(),
+        &creator_value.to_le_bytes(),
+        &opponent_value.to_le_bytes(),
+        &timestamp.to_le_bytes(),
+    ]);
+    
+    verify_ed25519_instruction(
+        instructions_sysvar,
+        2,
+        signature,
+        &oracle.to_bytes(),
+        message.as_ref(),
+    )
+    .map_err(|_| error!(DuelError::InvalidOracleSignature))
+}
+
+// Check that the instruction `distance` places before this one is an Ed25519
+// program verification of exactly this signature, public key and message,
+// all stored inline in that instruction
+fn verify_ed25519_instruction(
+    instructions_sysvar: &AccountInfo,
+    distance: usize,
+    signature: &[u8; 64],
+    public_key: &[u8; 32],
+    message: &[u8],
+) -> Result<()> {
+    let current_idx = load_current_index_checked(instructions_sysvar)? as usize;
+    require!(current_idx >= distance, DuelError::InvalidTradeProof);
+    let ix = load_instruction_at_checked(current_idx - distance, instructions_sysvar)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, DuelError::InvalidTradeProof);
+    
+    // Header: signature count and padding, then seven u16 offsets
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, DuelError::InvalidTradeProof);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let signature_offset = read_u16(2) as usize;
+    let public_key_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    require!(
+        read_u16(4) == u16::MAX && read_u16(8) == u16::MAX && read_u16(14) == u16::MAX,
+        DuelError::InvalidTradeProof
+    );
+    
+    require!(
+        data.get(signature_offset..signature_offset + 64) == Some(&signature[..])
+            && data.get(public_key_offset..public_key_offset + 32) == Some(&public_key[..])
+            && data.get(message_offset..message_offset + message_size) == Some(message),
+        DuelError::InvalidTradeProof
+    );
+    Ok(())
+}
+
+// Check that a position value did not move further than allowed since the last update
+fn check_update_delta(old_value: u64, new_value: u64, max_delta_bps: u32) -> Result<()> {
+    if old_value == 0 {
+        return Ok(());
+    }
+    
+    let delta_bps = (new_value as i128 - old_value as i128).abs() * 10000 / old_value as i128;
+    require!(
+        delta_bps <= max_delta_bps as i128,
+        DuelError::UpdateValueTooExtreme
+    );
+    Ok(())
+}
+
+// Check that no position exceeds its token's share of the total portfolio value
+fn check_allocation_limits(
+    limits: &[TokenAllocationLimit],
+    positions: &[TokenPosition],
+) -> Result<()> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+    
+    let total_value: u128 = positions.iter().map(|position| position.value as u128).sum();
+    if total_value == 0 {
+        return Ok(());
+    }
+    
+    for position in positions {
+        if let Some(limit) = limits.iter().find(|limit| limit.mint == position.mint) {
+            let weight_bps = position.value as u128 * 10000 / total_value;
+            require!(
+                weight_bps <= limit.max_weight_bps as u128,
+                DuelError::AllocationLimitExceeded
+            );
+        }
+    }
+    
+    Ok(())
+}
+
+// Return every deposited stake to its depositor
+fn refund_deposits<'info>(
+    duel: &mut Duel,
+    duel_key: Pubkey,
+    escrow_bump: u8,
+    duel_escrow: &AccountInfo<'info>,
+    creator: &AccountInfo<'info>,
+    opponent: Option<&AccountInfo<'info>>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let escrow_seeds = &[
+        b"escrow",
+        duel_key.as_ref(),
+        &[escrow_bump],
+    ];
+    let signer = &[&escrow_seeds[..]];
+    
+    if duel.creator_stake_deposited {
+        transfer_from_escrow(system_program, duel_escrow, creator, duel.stake_amount, signer)?;
+        duel.creator_stake_deposited = false;
+    }
+    
+    if duel.opponent_stake_deposited {
+        let opponent = opponent.ok_or(DuelError::OpponentAccountRequired)?;
+        transfer_from_escrow(system_program, duel_escrow, opponent, duel.stake_amount, signer)?;
+        duel.opponent_stake_deposited = false;
+    }
+    
+    Ok(())
+}
+
+// Close a WSOL duel's token escrow into its SOL escrow so the regular payout
+// and refund paths can distribute the stakes, then return the token account's
+// rent to the creator. Does nothing for SOL duels, or for WSOL duels without
+// deposits when no token escrow is passed.
+fn unwrap_wsol_escrow<'info>(
+    duel: &Duel,
+    wsol_escrow: Option<&Account<'info, TokenAccount>>,
+    token_program: Option<&Program<'info, Token>>,
+    duel_escrow: &AccountInfo<'info>,
+    creator: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    if !duel.use_wsol {
+        return Ok(());
+    }
+    let Some(wsol_escrow) = wsol_escrow else {
+        // Without deposits there may be no token escrow to close
+        require!(
+            !duel.creator_stake_deposited && !duel.opponent_stake_deposited,
+            DuelError::WsolEscrowRequired
+        );
+        return Ok(());
+    };
+    let token_program = token_program.ok_or(DuelError::WsolEscrowRequired)?;
+    
+    let rent_lamports = wsol_escrow.to_account_info().lamports() - wsol_escrow.amount;
+    token::close_account(CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token::CloseAccount {
+            account: wsol_escrow.to_account_info(),
+            destination: duel_escrow.clone(),
+            authority: duel_escrow.clone(),
+        },
+        signer_seeds,
+    ))?;
+    
+    transfer_from_escrow(system_program, duel_escrow, creator, rent_lamports, signer_seeds)
+}
+
+// Accounts that receive or release funds when a duel settles
+struct SettlementAccounts<'a, 'info> {
+    duel_escrow: &'a AccountInfo<'info>,
+    creator: &'a AccountInfo<'info>,
+    opponent: &'a AccountInfo<'info>,
+    treasury: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+}
+
+// Amounts computed during settlement that callers may need to record
+struct SettlementOutcome {
+    protocol_fee: u64,
+    creator_bonus: u64,
+    creator_pnl: i64,
+    opponent_pnl: i64,
+    payout: u64, // Paid to the winner, or to each side on a draw
+    effective_fee_bps: u16,
+}
+
+// Shared body of settle_duel and settle_duel_v2
+fn settle_expired_duel<'info>(
+    accounts: &mut SettleDuel<'info>,
+    escrow_bump: u8,
+    forced_winner: Option<DuelWinner>,
+    instruction_name: &str,
+) -> Result<()> {
+    let compute_start = log_compute!();
+    
+    require!(
+        !accounts.protocol.is_instruction_paused(IX_SETTLE_DUEL),
+        DuelError::InstructionPaused
+    );
+    
+    let duel = &mut accounts.duel;
+    let protocol = &mut accounts.protocol;
+    let clock = Clock::get()?;
+    
+    require!(duel.status == DuelStatus::Active, DuelError::InvalidStatus);
+    check_settleable(duel, clock.unix_timestamp, protocol.max_manual_duration)?;
+    
+    // Hold the re-entrancy guard across the payout CPIs below
+    require!(!duel.settling, DuelError::ReentrancyDetected);
+    duel.settling = true;
+    
+    let duel_key = duel.key();
+    let escrow_seeds = &[b"escrow".as_ref(), duel_key.as_ref(), &[escrow_bump]];
+    unwrap_wsol_escrow(
+        duel,
+        accounts.wsol_escrow.as_ref(),
+        accounts.token_program.as_ref(),
+        &accounts.duel_escrow.to_account_info(),
+        &accounts.creator.to_account_info(),
+        &accounts.system_program.to_account_info(),
+        &[&escrow_seeds[..]],
+    )?;
+    
+    let forced_winner = expired_settlement_winner(duel, forced_winner);
+    let outcome = execute_settlement(
+        duel,
+        duel_key,
+        escrow_bump,
+        protocol,
+        forced_winner,
+        SettlementAccounts {
+            duel_escrow: &accounts.duel_escrow.to_account_info(),
+            creator: &accounts.creator.to_account_info(),
+            opponent: &accounts.opponent.to_account_info(),
+            treasury: &accounts.treasury.to_account_info(),
+            system_program: &accounts.system_program.to_account_info(),
+        },
+    )?;
+    
+    record_duel_fee(&mut accounts.duel_fee, duel, duel_key, &outcome, clock.unix_timestamp);
+    
+    if let Some(heatmap) = accounts.heatmap.as_mut() {
+        let hour_of_week = heatmap_bucket(clock.unix_timestamp);
+        heatmap.hourly_settles[hour_of_week] = heatmap.hourly_settles[hour_of_week].saturating_add(1);
+    }
+    
+    record_participant_stats(
+        duel,
+        &mut accounts.creator_profile,
+        &mut accounts.opponent_profile,
+        &protocol.reputation_weights,
+    );
+    record_duel_insight(&mut accounts.duel_insight, duel, duel_key, &outcome, clock.unix_timestamp)?;
+    
+    // Credit the win on the duel's category leaderboard when one is supplied
+    if let Some(leaderboard) = &mut accounts.category_leaderboard {
+        let winner = match duel.winner {
+            DuelWinner::Creator => Some(duel.creator),
+            DuelWinner::Opponent => Some(duel.opponent),
+            _ => None,
+        };
+        if let Some(winner) = winner {
+            let elo = match &accounts.winner_profile {
+                Some(profile) => {
+                    require!(profile.wallet == winner, DuelError::Unauthorized);
+                    Some(profile.elo_rating)
+                }
+                None => None,
+            };
+            record_category_win(leaderboard, winner, elo);
+        }
+    }
+    
+    duel.settling = false;
+    log_compute!(instruction_name, compute_start);
+    
+    Ok(())
+}
+
+// Participants qualify for the loyalty discount once they have settled
+// `threshold` duels. Eligibility is fixed when each player joins, from the
+// profile they pass themselves, so no settler can withhold it.
+fn is_loyal(profile: Option<&PlayerProfile>, threshold: u32) -> bool {
+    profile.is_some_and(|profile| profile.duels_participated >= threshold)
+}
+
+// The winner, if they qualified for the loyalty discount when joining
+fn loyal_winner(duel: &Duel, winner: DuelWinner) -> Option<Pubkey> {
+    match winner {
+        DuelWinner::Creator if duel.creator_loyal => Some(duel.creator),
+        DuelWinner::Opponent if duel.opponent_loyal => Some(duel.opponent),
+        _ => None,
+    }
+}
+
+// Record the fee distribution; the account is never modified afterwards
+fn record_duel_fee(
+    duel_fee: &mut DuelFee,
+    duel: &Duel,
+    duel_key: Pubkey,
+    outcome: &SettlementOutcome,
+    now: i64,
+) {
+    duel_fee.duel = duel_key;
+    duel_fee.creator = duel.creator;
+    duel_fee.total_fee = outcome.protocol_fee;
+    duel_fee.protocol_share = outcome.protocol_fee - outcome.creator_bonus;
+    duel_fee.referrer_share = 0;
+    duel_fee.super_referrer_share = 0;
+    duel_fee.insurance_share = 0;
+    duel_fee.staker_share = 0;
+    duel_fee.effective_fee_bps = outcome.effective_fee_bps;
+    duel_fee.settled_at = now;
+}
+
+fn record_participant_stats(
+    duel: &Duel,
+    creator_profile: &mut Option<Account<PlayerProfile>>,
+    opponent_profile: &mut Option<Account<PlayerProfile>>,
+    weights: &[u64; 3],
+) {
+    if let Some(profile) = creator_profile.as_mut() {
+        record_settled_duel(profile, duel.winner == DuelWinner::Creator, duel.stake_amount, weights);
+    }
+    if let Some(profile) = opponent_profile.as_mut() {
+        record_settled_duel(profile, duel.winner == DuelWinner::Opponent, duel.stake_amount, weights);
+    }
+}
+
+// Protocol fee and payout for a settled duel: the winner's payout, or each
+// side's refund on a draw. A draw charges an even fee so the two refunds are
+// equal and the escrow pays out exactly the two stakes.
+fn settlement_split(stake_amount: u64, fee_bps: u16, draw: bool) -> Result<(u64, u64)> {
+    let total_stake = stake_amount.checked_mul(2).ok_or(DuelError::Overflow)?;
+    let protocol_fee = total_stake
+        .checked_mul(fee_bps as u64)
+        .ok_or(DuelError::Overflow)?
+        / 10000;
+    if draw {
+        let half_fee = protocol_fee / 2;
+        let refund = stake_amount
+            .checked_sub(half_fee)
+            .ok_or(DuelError::EscrowUnderflow)?;
+        Ok((half_fee * 2, refund))
+    } else {
+        let winner_payout = total_stake
+            .checked_sub(protocol_fee)
+            .ok_or(DuelError::Overflow)?;
+        Ok((protocol_fee, winner_payout))
+    }
+}
+
+// Whole-percent return of a payout on the stake behind it
+fn return_pct(payout: u64, stake: u64) -> Result<i64> {
+    let pct = (payout as i128 - stake as i128)
+        .checked_mul(100)
+        .and_then(|gain| gain.checked_div(stake as i128))
+        .ok_or(DuelError::Overflow)?;
+    i64::try_from(pct).map_err(|_| DuelError::Overflow.into())
+}
+
+// Cache the settlement analytics clients would otherwise derive themselves
+fn record_duel_insight(
+    duel_insight: &mut DuelInsight,
+    duel: &Duel,
+    duel_key: Pubkey,
+    outcome: &SettlementOutcome,
+    now: i64,
+) -> Result<()> {
+    let payout_pct = return_pct(outcome.payout, duel.stake_amount)?;
+    let (winner_return_pct, loser_loss_pct) = if duel.winner == DuelWinner::Draw {
+        (payout_pct, payout_pct)
+    } else {
+        (payout_pct, -100)
+    };
+    duel_insight.duel = duel_key;
+    duel_insight.creator = duel.creator;
+    duel_insight.creator_pnl_bps = outcome.creator_pnl;
+    duel_insight.opponent_pnl_bps = outcome.opponent_pnl;
+    duel_insight.pnl_margin_bps = outcome
+        .creator_pnl
+        .checked_sub(outcome.opponent_pnl)
+        .and_then(i64::checked_abs)
+        .ok_or(DuelError::PnlCalculationFailed)?;
+    duel_insight.winner_return_pct = winner_return_pct;
+    duel_insight.loser_loss_pct = loser_loss_pct;
+    duel_insight.duration_days = (duel.duration / (24 * 60 * 60)) as u16;
+    duel_insight.update_count = total_oracle_updates(duel);
+    duel_insight.settled_at = now;
+    duel_insight.creator_max_gain_bps =
+        safe_calculate_pnl(duel.creator_starting_value, duel.creator_peak_value)?;
+    duel_insight.creator_max_drawdown_bps =
+        -safe_calculate_pnl(duel.creator_starting_value, duel.creator_trough_value)?;
+    duel_insight.opponent_max_gain_bps =
+        safe_calculate_pnl(duel.opponent_starting_value, duel.opponent_peak_value)?;
+    duel_insight.opponent_max_drawdown_bps =
+        -safe_calculate_pnl(duel.opponent_starting_value, duel.opponent_trough_value)?;
+    
+    Ok(())
+}
+
+// Pay out an active duel from its escrow and record the result. Callers are
+// responsible for status, expiry and oracle update checks.
+fn execute_settlement(
+    duel: &mut Duel,
+    duel_key: Pubkey,
+    escrow_bump: u8,
+    protocol: &mut Protocol,
+    forced_winner: Option<DuelWinner>,
+    accounts: SettlementAccounts,
+) -> Result<SettlementOutcome> {
+    // Calculate PnL percentages
+    let creator_pnl = safe_calculate_pnl(duel.creator_starting_value, duel.creator_final_value)?;
+    let opponent_pnl =
+        safe_calculate_pnl(duel.opponent_starting_value, duel.opponent_final_value)?;
+    
+    // Fees distort percentages over short durations, so short HighestPnl
+    // duels are decided on absolute return instead
+    let mut win_condition = duel.win_condition;
+    if forced_winner.is_none()
+        && win_condition == WinCondition::HighestPnl
+        && duel.duration < protocol.absolute_pnl_duration_threshold
+    {
+        win_condition = WinCondition::HigherAbsoluteReturn;
+        emit!(WinConditionOverride {
+            duel: duel_key,
+            original: duel.win_condition,
+            applied: win_condition,
+        });
+    }
+    let (creator_score, opponent_score) = if win_condition == WinCondition::HigherAbsoluteReturn {
+        (
+            duel.creator_final_value as i128 - duel.creator_starting_value as i128,
+            duel.opponent_final_value as i128 - duel.opponent_starting_value as i128,
+        )
+    } else {
+        (creator_pnl as i128, opponent_pnl as i128)
+    };
+    
+    // Determine winner, unless the authority has chosen one
+    let winner = forced_winner.unwrap_or(if creator_score > opponent_score {
+        DuelWinner::Creator
+    } else if opponent_score > creator_score {
+        DuelWinner::Opponent
+    } else {
+        DuelWinner::Draw
+    });
+    let winner_account = match winner {
+        DuelWinner::Opponent => accounts.opponent,
+        _ => accounts.creator, // Draw handling
+    };
+    
+    // A loyal winner pays a discounted fee
+    let effective_fee_bps = match loyal_winner(duel, winner) {
+        Some(player) => {
+            let effective_fee_bps = protocol.fee_bps.saturating_sub(protocol.loyalty_discount_bps);
+            emit!(LoyaltyDiscountApplied {
+                duel: duel_key,
+                player,
+                effective_fee_bps,
+            });
+            effective_fee_bps
+        }
+        None => protocol.fee_bps,
+    };
+    
+    // Calculate payouts
+    let total_stake = duel.stake_amount.checked_mul(2).ok_or(DuelError::Overflow)?;
+    let (protocol_fee, payout) =
+        settlement_split(duel.stake_amount, effective_fee_bps, winner == DuelWinner::Draw)?;
+    let winner_payout = total_stake
+        .checked_sub(protocol_fee)
+        .ok_or(DuelError::Overflow)?;
+    
+    // The quick-accept bonus is carved out of the fee before it reaches the
+    // treasury. It was fixed at accept time while the fee may since have been
+    // discounted or lowered, so it is capped at the fee rather than blocking
+    // settlement.
+    let creator_bonus = match protocol.bonus_condition {
+        QuickAcceptBonusCondition::Always => duel.creator_quick_accept_bonus,
+        QuickAcceptBonusCondition::CreatorWins if winner == DuelWinner::Creator => {
+            duel.creator_quick_accept_bonus
+        }
+        QuickAcceptBonusCondition::CreatorWins => 0,
+    };
+    if creator_bonus > protocol_fee {
+        msg!("Creator bonus {} capped at protocol fee {}", creator_bonus, protocol_fee);
+    }
+    let creator_bonus = creator_bonus.min(protocol_fee);
+    
+    // Use proper CPI transfers instead of direct lamport manipulation
+    let escrow_seeds = &[
+        b"escrow",
+        duel_key.as_ref(),
+        &[escrow_bump],
+    ];
+    let signer = &[&escrow_seeds[..]];
+    
+    // Transfer protocol fee to treasury
+    transfer_from_escrow(
+        accounts.system_program,
+        accounts.duel_escrow,
+        accounts.treasury,
+        protocol_fee - creator_bonus,
+        signer,
+    )?;
+    
+    if creator_bonus > 0 {
+        transfer_from_escrow(
+            accounts.system_program,
+            accounts.duel_escrow,
+            accounts.creator,
+            creator_bonus,
+            signer,
+        )?;
+    }
+    
+    // Transfer winnings
+    if winner == DuelWinner::Draw {
+        // Return stakes minus half fee each
+        transfer_from_escrow(
+            accounts.system_program,
+            accounts.duel_escrow,
+            accounts.creator,
+            payout,
+            signer,
+        )?;
+        transfer_from_escrow(
+            accounts.system_program,
+            accounts.duel_escrow,
+            accounts.opponent,
+            payout,
+            signer,
+        )?;
+    } else {
+        transfer_from_escrow(
+            accounts.system_program,
+            accounts.duel_escrow,
+            winner_account,
+            payout,
+            signer,
+        )?;
+    }
+    
+    // Update duel status
+    duel.status = DuelStatus::Settled;
+    duel.winner = winner;
+    
+    // Update protocol stats (saturating; they never gate settlement)
+    protocol.total_volume = protocol.total_volume.saturating_add(total_stake);
+    protocol.total_fees_collected = protocol
+        .total_fees_collected
+        .saturating_add(protocol_fee - creator_bonus);
+    release_active_stake(protocol, duel);
+    
+    emit!(DuelSettled {
+        duel: duel_key,
+        winner,
+        creator_pnl,
+        opponent_pnl,
+        winner_payout,
+        protocol_fee,
+    });
+    
+    Ok(SettlementOutcome {
+        protocol_fee,
+        creator_bonus,
+        creator_pnl,
+        opponent_pnl,
+        payout,
+        effective_fee_bps,
+    })
+}
+
+// Duel settings supplied by create_duel or a template
+struct NewDuelConfig {
+    stake_amount: u64,
+    duration_seconds: i64,
+    allowed_tokens: Vec<Pubkey>,
+    category: DuelCategory,
+    creator_token_allocation: Vec<TokenAllocationLimit>,
+    win_condition: WinCondition,
+    use_wsol: bool,
+    expiry_mode: ExpiryMode,
+    duel_uses_sol_natively: bool,
+}
+
+// Validate a new duel's settings, charge the creation fee and initialize the
+// duel account
+fn init_duel(
+    accounts: &mut CreateDuel,
+    nonce: u64,
+    client_version: u32,
+    config: NewDuelConfig,
+) -> Result<()> {
+    let NewDuelConfig {
+        stake_amount,
+        duration_seconds,
+        allowed_tokens,
+        category,
+        creator_token_allocation,
+        win_condition,
+        use_wsol,
+        expiry_mode,
+        duel_uses_sol_natively,
+    } = config;
+    
+    require!(
+        !accounts.protocol.is_instruction_paused(IX_CREATE_DUEL),
+        DuelError::InstructionPaused
+    );
+    check_client_version(&accounts.protocol, client_version)?;
+    require!(
+        !accounts.protocol.require_profile_for_participation || accounts.creator_profile.is_some(),
+        DuelError::ProfileRequired
+    );
+    
+    let duel = &mut accounts.duel;
+    let clock = Clock::get()?;
+    
+    // Refuse to record timestamps from an uninitialized or skewed clock
+    if clock.unix_timestamp <= MIN_VALID_TIMESTAMP || clock.unix_timestamp >= MAX_VALID_TIMESTAMP {
+        msg!("Invalid clock timestamp: {}", clock.unix_timestamp);
+        return Err(DuelError::InvalidTimestamp.into());
+    }
+    
+    let min_duration = accounts.protocol.min_duel_duration;
+    let max_duration = accounts.protocol.max_duel_duration;
+    if duration_seconds < min_duration {
+        msg!("Duration too short: minimum is {} seconds", min_duration);
+        return Err(DuelError::DurationTooShort.into());
+    }
+    if duration_seconds > max_duration {
+        msg!("Duration too long: maximum is {} seconds", max_duration);
+        return Err(DuelError::DurationTooLong.into());
+    }
+    require!(
+        accounts.protocol.category_enabled[category as usize],
+        DuelError::CategoryDisabled
+    );
+    if let WinCondition::FirstToTarget { target_pnl_bps } = win_condition {
+        require!(target_pnl_bps > 0, DuelError::InvalidWinCondition);
+    }
+    
+    // The escrow is a data-less system account; keep every deposit above
+    // its rent-exempt minimum so a partially funded escrow is never reaped
+    let rent = Rent::get()?;
+    require!(
+        stake_amount >= rent.minimum_balance(0),
+        DuelError::StakeBelowRentExempt
+    );
+    
+    // Fall back to the protocol defaults when no tokens are specified
+    let allowed_tokens = if allowed_tokens.is_empty() {
+        let defaults = &accounts.protocol.default_allowed_tokens;
+        require!(!defaults.is_empty(), DuelError::NoDefaultTokensSet);
+        defaults.clone()
+    } else {
+        allowed_tokens
+    };
+    
+    let allowed_tokens = normalize_allowed_tokens(allowed_tokens);
+    
+    // Oracles value the native mint differently from SPL tokens, so it is
+    // only tradable in duels that opt in to native SOL valuation
+    require!(
+        !allowed_tokens.contains(&token::spl_token::native_mint::ID) || duel_uses_sol_natively,
+        DuelError::NativeMintInAllowedTokens
+    );
+    
+    require!(
+        creator_token_allocation.len() <= MAX_TOKEN_ALLOCATION_LIMITS,
+        DuelError::TooManyAllocationLimits
+    );
+    require!(
+        creator_token_allocation
+            .iter()
+            .all(|limit| limit.max_weight_bps <= 10000),
+        DuelError::InvalidAllocationLimit
+    );
+    
+    // Spam deterrent: charge the creation fee up front; it is never refunded
+    let creation_fee = accounts.protocol.creation_fee_lamports;
+    if creation_fee > 0 {
+        require!(
+            accounts.creator.lamports() >= creation_fee,
+            DuelError::InsufficientFundsForCreationFee
+        );
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: accounts.creator.to_account_info(),
+                    to: accounts.treasury.to_account_info(),
+                },
+            ),
+            creation_fee,
+        )?;
+        
+        emit!(CreationFeePaid {
+            duel: duel.key(),
+            creator: accounts.creator.key(),
+            fee_amount: creation_fee,
+        });
+    }
     
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    duel.creator = accounts.creator.key();
+    duel.opponent = Pubkey::default(); // To be filled when accepted
+    duel.stake_amount = stake_amount;
+    duel.created_at = clock.unix_timestamp;
+    duel.start_time = 0;
+    duel.end_time = 0;
+    duel.duration = duration_seconds;
+    duel.status = DuelStatus::Pending;
+    duel.creator_stake_deposited = false;
+    duel.opponent_stake_deposited = false;
+    duel.allowed_tokens = allowed_tokens;
+    duel.creator_starting_value = 0;
+    duel.opponent_starting_value = 0;
+    duel.creator_final_value = 0;
+    duel.opponent_final_value = 0;
+    duel.winner = DuelWinner::None;
+    duel.stake_locked_until = 0;
+    duel.pause_request_creator = false;
+    duel.pause_request_opponent = false;
+    duel.resume_request_creator = false;
+    duel.resume_request_opponent = false;
+    duel.pause_start = 0;
+    duel.category = category;
+    duel.oracle_update_counts = Vec::new();
+    duel.creator_alias = accounts
+        .creator_profile
+        .as_ref()
+        .map(|profile| profile.display_name)
+        .unwrap_or_default();
+    duel.opponent_alias = [0; 32];
+    duel.warning_notice_present = false;
+    duel.creator_token_allocation = creator_token_allocation;
+    duel.creator_deposit_at = 0;
+    duel.opponent_deposit_at = 0;
+    duel.creator_quick_accept_bonus = 0;
+    duel.win_condition = win_condition;
+    duel.last_update_timestamp = 0;
+    duel.use_wsol = use_wsol;
+    duel.both_deposited_at = 0;
+    duel.oracle_init_deadline = 0;
+    duel.creator_peak_value = 0;
+    duel.opponent_peak_value = 0;
+    duel.creator_trough_value = 0;
+    duel.opponent_trough_value = 0;
+    duel.expiry_mode = expiry_mode;
+    duel.oracle_finalized = false;
+    duel.nonce = nonce;
+    duel.settling = false;
+    // One oracle update per update interval of the duration, at least one
+    let update_interval = accounts.protocol.min_update_interval_seconds.max(1);
+    duel.required_oracle_updates = (duration_seconds / update_interval).clamp(1, u8::MAX as i64) as u8;
+    duel.creator_loyal = is_loyal(
+        accounts.creator_profile.as_deref(),
+        accounts.protocol.loyalty_discount_threshold,
+    );
+    duel.opponent_loyal = false;
     
-    /// CHECK: Treasury account for fees
-    pub treasury: UncheckedAccount<'info>,
+    if let Some(heatmap) = accounts.heatmap.as_mut() {
+        let hour_of_week = heatmap_bucket(clock.unix_timestamp);
+        heatmap.hourly_counts[hour_of_week] = heatmap.hourly_counts[hour_of_week].saturating_add(1);
+    }
     
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-pub struct CreateDuel<'info> {
-    #[account(
-        init,
-        payer = creator,
-        space = 8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 1 + 1 + 1 + 1 + 4 + (32 * 10) + 8 + 8 + 8 + 8,
-        seeds = [b"duel", protocol.total_duels.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub duel: Account<'info, Duel>,
+    if let Some(price_index) = accounts.price_index.as_mut() {
+        price_index.duel = duel.key();
+        price_index.token_feeds = Vec::new();
+    }
     
-    #[account(mut)]
-    pub protocol: Account<'info, Protocol>,
+    let audit_log = &mut accounts.audit_log;
+    audit_log.duel = duel.key();
+    audit_log.entries = Vec::new();
+    audit_log.next_index = 0;
+    audit_log.total_entries = 0;
     
-    #[account(mut)]
-    pub creator: Signer<'info>,
+    // Increment protocol stats
+    let protocol = &mut accounts.protocol;
+    protocol.total_duels = protocol
+        .total_duels
+        .checked_add(1)
+        .ok_or(DuelError::TotalDuelsOverflow)?;
+    protocol.total_fees_collected = protocol.total_fees_collected.saturating_add(creation_fee);
+    track_duel_status(protocol, None, DuelStatus::Pending);
     
-    pub system_program: Program<'info, System>,
+    let duel = &accounts.duel;
+    emit!(DuelCreated {
+        duel: duel.key(),
+        duel_id: duel.nonce,
+        creator: duel.creator,
+        stake_amount: duel.stake_amount,
+        duration: duel.duration,
+        allowed_tokens: duel.allowed_tokens.clone(),
+        category: duel.category,
+        invited_opponent: None, // Duels are open challenges; there is no invite yet
+        created_at: duel.created_at,
+        creator_alias: duel.creator_alias,
+    });
+    
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct AcceptDuel<'info> {
-    #[account(mut)]
-    pub duel: Account<'info, Duel>,
-    
-    #[account(mut)]
-    pub opponent: Signer<'info>,
+// Allocate a duel PDA with the given seeds (bump included), paid for by `payer`
+fn create_duel_account<'info>(
+    system_program: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    duel: &AccountInfo<'info>,
+    duel_seeds: &[&[u8]],
+) -> Result<()> {
+    anchor_lang::system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::CreateAccount {
+                from: payer.clone(),
+                to: duel.clone(),
+            },
+            &[duel_seeds],
+        ),
+        Rent::get()?.minimum_balance(Duel::space()),
+        Duel::space() as u64,
+        &crate::ID,
+    )
 }
 
-#[derive(Accounts)]
-pub struct DepositStake<'info> {
-    #[account(mut)]
-    pub duel: Account<'info, Duel>,
+// Allocate and initialize a funded duel's timer PDA, paid for by `payer`
+fn create_duel_timer<'info>(
+    system_program: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    duel_timer: &AccountInfo<'info>,
+    duel: &Account<'info, Duel>,
+    bump: u8,
+) -> Result<()> {
+    let duel_key = duel.key();
+    let timer_seeds = &[b"timer".as_ref(), duel_key.as_ref(), &[bump]];
+    anchor_lang::system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::CreateAccount {
+                from: payer.clone(),
+                to: duel_timer.clone(),
+            },
+            &[&timer_seeds[..]],
+        ),
+        Rent::get()?.minimum_balance(DuelTimer::space()),
+        DuelTimer::space() as u64,
+        &crate::ID,
+    )?;
     
-    #[account(
-        mut,
-        seeds = [b"escrow", duel.key().as_ref()],
-        bump
-    )]
-    /// CHECK: Escrow account for holding stakes
-    pub duel_escrow: UncheckedAccount<'info>,
+    let timer = DuelTimer {
+        duel: duel_key,
+        rent_payer: payer.key(),
+        start_time: 0,
+        end_time: 0,
+        duration: duel.duration,
+        extension_count: 0,
+        total_extension_seconds: 0,
+    };
+    let mut data = duel_timer.try_borrow_mut_data()?;
+    timer.try_serialize(&mut &mut data[..])?;
+    Ok(())
+}
+
+// Allocate a duel's audit log PDA for duels created outside CreateDuel, paid
+// for by `payer`, and record the creating call as its first entry
+fn create_audit_log<'info>(
+    system_program: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    audit_log: &AccountInfo<'info>,
+    duel_key: Pubkey,
+    instruction_name: &str,
+    instruction_args_hash: [u8; 32],
+) -> Result<()> {
+    let (audit_key, bump) =
+        Pubkey::find_program_address(&[b"audit", duel_key.as_ref()], &crate::ID);
+    require_keys_eq!(audit_log.key(), audit_key, DuelError::InvalidAuditLog);
     
-    #[account(mut)]
-    pub depositor: Signer<'info>,
+    let audit_seeds = &[b"audit".as_ref(), duel_key.as_ref(), &[bump]];
+    anchor_lang::system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::CreateAccount {
+                from: payer.clone(),
+                to: audit_log.clone(),
+            },
+            &[&audit_seeds[..]],
+        ),
+        Rent::get()?.minimum_balance(DuelAuditLog::space()),
+        DuelAuditLog::space() as u64,
+        &crate::ID,
+    )?;
     
-    pub system_program: Program<'info, System>,
+    let mut log = DuelAuditLog {
+        duel: duel_key,
+        entries: Vec::new(),
+        next_index: 0,
+        total_entries: 0,
+    };
+    record_audit(&mut log, instruction_name, payer.key(), instruction_args_hash)?;
+    let mut data = audit_log.try_borrow_mut_data()?;
+    log.try_serialize(&mut &mut data[..])?;
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct UpdatePositions<'info> {
-    #[account(mut)]
-    pub duel: Account<'info, Duel>,
-    
-    #[account(mut)]
-    pub oracle: Signer<'info>, // In production, verify this is authorized oracle
+// An accepted duel between two known players, awaiting both stakes
+fn accepted_duel(
+    creator: Pubkey,
+    opponent: Pubkey,
+    stake_amount: u64,
+    duration: i64,
+    allowed_tokens: Vec<Pubkey>,
+    now: i64,
+) -> Duel {
+    Duel {
+        creator,
+        opponent,
+        stake_amount,
+        created_at: now,
+        start_time: 0,
+        end_time: 0,
+        duration,
+        status: DuelStatus::Accepted,
+        winner: DuelWinner::None,
+        creator_stake_deposited: false,
+        opponent_stake_deposited: false,
+        allowed_tokens,
+        creator_starting_value: 0,
+        opponent_starting_value: 0,
+        creator_final_value: 0,
+        opponent_final_value: 0,
+        stake_locked_until: 0,
+        pause_request_creator: false,
+        pause_request_opponent: false,
+        resume_request_creator: false,
+        resume_request_opponent: false,
+        pause_start: 0,
+        category: DuelCategory::Custom,
+        oracle_update_counts: Vec::new(),
+        creator_alias: [0; 32],
+        opponent_alias: [0; 32],
+        warning_notice_present: false,
+        creator_token_allocation: Vec::new(),
+        creator_deposit_at: 0,
+        opponent_deposit_at: 0,
+        creator_quick_accept_bonus: 0,
+        win_condition: WinCondition::HighestPnl,
+        last_update_timestamp: 0,
+        use_wsol: false,
+        both_deposited_at: 0,
+        oracle_init_deadline: 0,
+        creator_peak_value: 0,
+        opponent_peak_value: 0,
+        creator_trough_value: 0,
+        opponent_trough_value: 0,
+        expiry_mode: ExpiryMode::TimeBased,
+        oracle_finalized: false,
+        nonce: 0,
+        settling: false,
+        required_oracle_updates: 0, // Ranked and pool duels have no minimum
+        creator_loyal: false,
+        opponent_loyal: false,
+    }
 }
 
-#[derive(Accounts)]
-pub struct SettleDuel<'info> {
-    #[account(mut)]
-    pub duel: Account<'info, Duel>,
-    
-    #[account(mut)]
-    pub protocol: Account<'info, Protocol>,
+// Called once both stakes are in escrow: hand the duel to the oracle, or
+// lock it first when the protocol requires a waiting period
+fn activate_funded_duel(duel: &mut Duel, protocol: &mut Protocol, now: i64) -> Result<()> {
+    duel.both_deposited_at = now;
+    add_active_stake(protocol, duel)?;
+    track_duel_status(protocol, Some(duel.status), DuelStatus::Locked);
+    if protocol.stake_lock_period_seconds > 0 {
+        duel.status = DuelStatus::Locked;
+        duel.stake_locked_until = now + protocol.stake_lock_period_seconds;
+        
+        msg!("Stakes locked until {}", duel.stake_locked_until);
+    } else {
+        await_oracle_init(duel, protocol, now);
+    }
+    Ok(())
+}
+
+// Ranked matches are created already funded, so the duel is counted as
+// accepted before activation moves it out of that counter again
+fn activate_ranked_duel(duel: &mut Duel, protocol: &mut Protocol, now: i64) -> Result<()> {
+    track_duel_status(protocol, None, DuelStatus::Accepted);
+    activate_funded_duel(duel, protocol, now)
+}
+
+fn add_active_stake(protocol: &mut Protocol, duel: &Duel) -> Result<()> {
+    let delta = duel.stake_amount.checked_mul(2).ok_or(DuelError::Overflow)?;
+    protocol.total_active_stake = protocol
+        .total_active_stake
+        .checked_add(delta)
+        .ok_or(DuelError::Overflow)?;
+    protocol.peak_active_stake = protocol.peak_active_stake.max(protocol.total_active_stake);
     
-    #[account(
-        mut,
-        seeds = [b"escrow", duel.key().as_ref()],
-        bump
-    )]
-    /// CHECK: Escrow account for holding stakes
-    pub duel_escrow: UncheckedAccount<'info>,
+    emit!(ActiveStakeUpdated {
+        new_total: protocol.total_active_stake,
+        delta: delta as i64,
+    });
+    Ok(())
+}
+
+// Move a duel between the pending/accepted counters close_protocol checks.
+// Decrements saturate, since duels created before the counters existed were
+// never added.
+fn track_duel_status(protocol: &mut Protocol, from: Option<DuelStatus>, to: DuelStatus) {
+    match from {
+        Some(DuelStatus::Pending) => protocol.pending_duels = protocol.pending_duels.saturating_sub(1),
+        Some(DuelStatus::Accepted) => protocol.accepted_duels = protocol.accepted_duels.saturating_sub(1),
+        _ => {}
+    }
+    match to {
+        DuelStatus::Pending => protocol.pending_duels = protocol.pending_duels.saturating_add(1),
+        DuelStatus::Accepted => protocol.accepted_duels = protocol.accepted_duels.saturating_add(1),
+        _ => {}
+    }
+}
+
+// Saturating, since duels funded before the counter existed were never added
+fn release_active_stake(protocol: &mut Protocol, duel: &Duel) {
+    let delta = duel.stake_amount.saturating_mul(2);
+    protocol.total_active_stake = protocol.total_active_stake.saturating_sub(delta);
     
-    #[account(mut)]
-    /// CHECK: Creator account to receive winnings
-    pub creator: UncheckedAccount<'info>,
+    emit!(ActiveStakeUpdated {
+        new_total: protocol.total_active_stake,
+        delta: -(delta as i64),
+    });
+}
+
+fn await_oracle_init(duel: &mut Duel, protocol: &Protocol, now: i64) {
+    duel.status = DuelStatus::PendingOracle;
+    duel.oracle_init_deadline = now + protocol.oracle_init_deadline_seconds;
     
-    #[account(mut)]
-    /// CHECK: Opponent account to receive winnings
-    pub opponent: UncheckedAccount<'info>,
+    msg!("Awaiting oracle initialization until {}", duel.oracle_init_deadline);
+}
+
+fn start_duel(duel: &mut Duel, now: i64, creator_starting_value: u64, opponent_starting_value: u64) {
+    duel.status = DuelStatus::Active;
+    duel.start_time = now;
+    if duel.expiry_mode == ExpiryMode::TimeBased {
+        duel.end_time = now + duel.duration;
+    }
     
-    #[account(mut)]
-    /// CHECK: Treasury account for fees
-    pub treasury: UncheckedAccount<'info>,
+    duel.creator_starting_value = creator_starting_value;
+    duel.opponent_starting_value = opponent_starting_value;
+    duel.creator_peak_value = creator_starting_value;
+    duel.opponent_peak_value = opponent_starting_value;
+    duel.creator_trough_value = creator_starting_value;
+    duel.opponent_trough_value = opponent_starting_value;
     
-    pub system_program: Program<'info, System>,
+    match duel.expiry_mode {
+        ExpiryMode::TimeBased => msg!("Duel started! Trading period ends at {}", duel.end_time),
+        ExpiryMode::ManualOracle => msg!("Duel started! Trading ends on the oracle's final update"),
+    }
 }
 
-#[derive(Accounts)]
-pub struct CancelDuel<'info> {
-    #[account(mut)]
-    pub duel: Account<'info, Duel>,
+fn check_trading_open(duel: &Duel, now: i64) -> Result<()> {
+    match duel.expiry_mode {
+        ExpiryMode::TimeBased => require!(now <= duel.end_time, DuelError::DuelExpired),
+        ExpiryMode::ManualOracle => require!(!duel.oracle_finalized, DuelError::DuelExpired),
+    }
+    Ok(())
+}
+
+// End of the trading period, known only for Active time-based duels
+fn estimated_end_time(duel: &Duel) -> Option<i64> {
+    match (duel.status, duel.expiry_mode) {
+        (DuelStatus::Active, ExpiryMode::TimeBased) => Some(duel.end_time),
+        _ => None,
+    }
+}
+
+fn is_expired(duel: &Duel, clock: &Clock) -> bool {
+    estimated_end_time(duel).is_some_and(|end_time| clock.unix_timestamp >= end_time)
+}
+
+// Position updates received across all oracles
+fn total_oracle_updates(duel: &Duel) -> u32 {
+    duel
+        .oracle_update_counts
+        .iter()
+        .fold(0u32, |total, entry| total.saturating_add(entry.count))
+}
+
+// settle_duel's expected compute cost for the given path
+fn settle_compute_estimate(is_draw: bool, use_wsol: bool, with_category_leaderboard: bool) -> u64 {
+    let mut estimated_cus = BASE_SETTLE_CU;
+    if is_draw {
+        estimated_cus += DRAW_EXTRA_CU;
+    }
+    if use_wsol {
+        estimated_cus += WSOL_UNWRAP_EXTRA_CU;
+    }
+    if with_category_leaderboard {
+        estimated_cus += CATEGORY_LEADERBOARD_EXTRA_CU;
+    }
+    estimated_cus
+}
+
+// Sort and deduplicate a token set, so the same set always has the same
+// encoding regardless of the order it was listed in
+fn normalize_allowed_tokens(mut tokens: Vec<Pubkey>) -> Vec<Pubkey> {
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+// Reject remaining accounts that cannot be a Duel before deserializing them,
+// so another program-owned account type is never read as one
+fn check_duel_account_data(account: &AccountInfo) -> Result<()> {
+    require!(
+        (MIN_DUEL_SIZE..=MAX_DUEL_SIZE).contains(&account.data_len()),
+        DuelError::AccountDataLengthMismatch
+    );
+    let data = account.try_borrow_data()?;
+    require!(data[..8] == *Duel::DISCRIMINATOR, DuelError::WrongAccountType);
+    Ok(())
+}
+
+// A duel may only settle once it has received its required oracle updates
+fn check_oracle_updates(duel: &Duel) -> Result<()> {
+    let update_count = total_oracle_updates(duel);
+    if update_count < duel.required_oracle_updates as u32 {
+        msg!(
+            "Duel has {} oracle updates; {} required",
+            update_count,
+            duel.required_oracle_updates
+        );
+        return err!(DuelError::InsufficientOracleUpdates);
+    }
+    Ok(())
+}
+
+// Winner to settle an expired duel with. Reaching the end of the duel cannot
+// depend on the oracle, so a duel short of its required updates is settled as
+// a draw rather than left holding the stakes.
+fn expired_settlement_winner(duel: &Duel, forced_winner: Option<DuelWinner>) -> Option<DuelWinner> {
+    if forced_winner.is_some() {
+        return forced_winner;
+    }
+    match check_oracle_updates(duel) {
+        Ok(()) => None,
+        Err(_) => Some(DuelWinner::Draw),
+    }
+}
+
+// The PnL that settles a FirstToTarget duel early, if either side has reached
+// the target. Early settlement is driven by the update itself, so it does not
+// wait for the duel's required oracle updates.
+fn early_settlement_pnl(duel: &Duel, creator_value: u64, opponent_value: u64) -> Result<Option<i64>> {
+    let WinCondition::FirstToTarget { target_pnl_bps } = duel.win_condition else {
+        return Ok(None);
+    };
+    let creator_pnl = safe_calculate_pnl(duel.creator_starting_value, creator_value)?;
+    let opponent_pnl = safe_calculate_pnl(duel.opponent_starting_value, opponent_value)?;
+    let triggering_pnl = creator_pnl.max(opponent_pnl);
+    Ok((triggering_pnl >= target_pnl_bps).then_some(triggering_pnl))
+}
+
+// Price feeds can change until trading starts
+fn check_price_index_editable(duel: &Duel) -> Result<()> {
+    require!(
+        matches!(
+            duel.status,
+            DuelStatus::Pending | DuelStatus::Accepted | DuelStatus::Locked | DuelStatus::PendingOracle
+        ),
+        DuelError::InvalidStatus
+    );
+    Ok(())
+}
+
+// The oracle values portfolios with the index, so authorized oracles may
+// maintain it as well as the duel's creator
+fn can_edit_price_index(duel: &Duel, protocol: &Protocol, signer: &Pubkey) -> bool {
+    *signer == duel.creator || protocol.authorized_oracles.contains(signer)
+}
+
+// Manual duels settle once the oracle finalizes them, or by anyone after the
+// protocol's safety cap so stakes cannot be stranded by an absent oracle
+fn check_settleable(duel: &Duel, now: i64, max_manual_duration: i64) -> Result<()> {
+    match duel.expiry_mode {
+        ExpiryMode::TimeBased => require!(now >= duel.end_time, DuelError::DuelNotExpired),
+        ExpiryMode::ManualOracle => require!(
+            duel.oracle_finalized || now >= duel.start_time + max_manual_duration,
+            DuelError::CannotSettleManualMode
+        ),
+    }
+    Ok(())
+}
+
+// Update a participant's profile stats after settlement
+fn record_settled_duel(profile: &mut PlayerProfile, won: bool, stake_amount: u64, weights: &[u64; 3]) {
+    profile.duels_participated = profile.duels_participated.saturating_add(1);
+    profile.win_streak = if won { profile.win_streak.saturating_add(1) } else { 0 };
+    profile.total_volume = profile.total_volume.saturating_add(stake_amount);
     
-    pub creator: Signer<'info>,
+    let old_score = profile.reputation_score;
+    profile.reputation_score = reputation_score(profile, weights);
+    
+    emit!(ReputationUpdated {
+        player: profile.wallet,
+        old_score,
+        new_score: profile.reputation_score,
+    });
 }
 
-// Events
-#[event]
-pub struct PositionUpdate {
-    pub duel: Pubkey,
-    pub creator_value: u64,
-    pub opponent_value: u64,
-    pub timestamp: i64,
+fn reputation_score(profile: &PlayerProfile, weights: &[u64; 3]) -> u64 {
+    (profile.elo_rating as u64)
+        .saturating_mul(weights[0])
+        .saturating_add((profile.win_streak as u64).saturating_mul(weights[1]))
+        .saturating_add(profile.total_volume / weights[2].max(1))
 }
 
-#[event]
-pub struct DuelSettled {
-    pub duel: Pubkey,
-    pub winner: DuelWinner,
-    pub creator_pnl: i64,
-    pub opponent_pnl: i64,
-    pub winner_payout: u64,
-    pub protocol_fee: u64,
+// Hour-of-week heatmap bucket for a unix timestamp
+fn heatmap_bucket(unix_timestamp: i64) -> usize {
+    (unix_timestamp / 3600).rem_euclid(HEATMAP_BUCKETS as i64) as usize
 }
 
-// Error codes
-#[error_code]
-pub enum DuelError {
-    #[msg("Invalid duel status for this operation")]
-    InvalidStatus,
-    #[msg("Duel has already been accepted")]
-    DuelAlreadyAccepted,
-    #[msg("Not a participant in this duel")]
-    NotParticipant,
-    #[msg("Duel has expired")]
-    DuelExpired,
-    #[msg("Duel has not expired yet")]
-    DuelNotExpired,
-    #[msg("Cannot cancel duel in current status")]
-    CannotCancel,
-    #[msg("Unauthorized action")]
-    Unauthorized,
+// PnL in basis points, or Ok(0) when there is no starting value. Fails with
+// PnlCalculationFailed rather than wrapping when the result does not fit in i64.
+fn safe_calculate_pnl(starting_value: u64, final_value: u64) -> Result<i64> {
+    unsafe_calculate_pnl(starting_value, final_value)
+        .ok_or_else(|| error!(DuelError::PnlCalculationFailed))
 }
 
-// Helper functions
-fn calculate_pnl(starting_value: u64, final_value: u64) -> i64 {
+// Raw PnL arithmetic; None on overflow. Use safe_calculate_pnl instead.
+fn unsafe_calculate_pnl(starting_value: u64, final_value: u64) -> Option<i64> {
     if starting_value == 0 {
-        return 0;
+        return Some(0);
+    }
+    
+    let pnl = (final_value as i128 - starting_value as i128)
+        .checked_mul(10000)?
+        .checked_div(starting_value as i128)?;
+    i64::try_from(pnl).ok()
+}   
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn duel_error(error: DuelError) -> Error {
+        error.into()
+    }
+    
+    fn test_duel() -> Duel {
+        accepted_duel(Pubkey::new_unique(), Pubkey::new_unique(), 1_000_000, 3600, Vec::new(), 0)
+    }
+    
+    fn test_protocol() -> Protocol {
+        let mut data = vec![0; Protocol::space()];
+        data[..8].copy_from_slice(Protocol::DISCRIMINATOR);
+        Protocol::try_deserialize(&mut &data[..]).unwrap()
+    }
+    
+    fn check_data(mut data: Vec<u8>) -> Result<()> {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let info = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &crate::ID, false, 0);
+        check_duel_account_data(&info)
+    }
+    
+    #[test]
+    fn update_delta_is_capped() {
+        assert!(check_update_delta(0, u64::MAX, 0).is_ok()); // First update
+        assert!(check_update_delta(1_000, 1_500, 5_000).is_ok());
+        assert!(check_update_delta(1_000, 500, 5_000).is_ok());
+        assert_eq!(
+            check_update_delta(1_000, 1_501, 5_000).unwrap_err(),
+            duel_error(DuelError::UpdateValueTooExtreme)
+        );
+        assert_eq!(
+            check_update_delta(1_000, 499, 5_000).unwrap_err(),
+            duel_error(DuelError::UpdateValueTooExtreme)
+        );
+    }
+    
+    #[test]
+    fn allocation_limits_cap_listed_mints() {
+        let capped = Pubkey::new_unique();
+        let free = Pubkey::new_unique();
+        let limits = [TokenAllocationLimit { mint: capped, max_weight_bps: 5_000 }];
+        
+        let within = [
+            TokenPosition { mint: capped, value: 500 },
+            TokenPosition { mint: free, value: 500 },
+        ];
+        assert!(check_allocation_limits(&limits, &within).is_ok());
+        assert!(check_allocation_limits(&[], &within).is_ok());
+        
+        let over = [
+            TokenPosition { mint: capped, value: 501 },
+            TokenPosition { mint: free, value: 499 },
+        ];
+        assert_eq!(
+            check_allocation_limits(&limits, &over).unwrap_err(),
+            duel_error(DuelError::AllocationLimitExceeded)
+        );
+        
+        // Unlisted mints and empty portfolios are unconstrained
+        let unlisted = [TokenPosition { mint: free, value: 1_000 }];
+        assert!(check_allocation_limits(&limits, &unlisted).is_ok());
+        let empty = [TokenPosition { mint: capped, value: 0 }];
+        assert!(check_allocation_limits(&limits, &empty).is_ok());
+    }
+    
+    #[test]
+    fn time_based_duels_settle_at_end_time() {
+        let mut duel = test_duel();
+        duel.end_time = 100;
+        
+        assert_eq!(
+            check_settleable(&duel, 99, 1_000).unwrap_err(),
+            duel_error(DuelError::DuelNotExpired)
+        );
+        assert!(check_settleable(&duel, 100, 1_000).is_ok());
+    }
+    
+    #[test]
+    fn manual_duels_settle_when_finalized_or_capped() {
+        let mut duel = test_duel();
+        duel.expiry_mode = ExpiryMode::ManualOracle;
+        duel.start_time = 50;
+        
+        assert_eq!(
+            check_settleable(&duel, 1_049, 1_000).unwrap_err(),
+            duel_error(DuelError::CannotSettleManualMode)
+        );
+        assert!(check_settleable(&duel, 1_050, 1_000).is_ok());
+        
+        duel.oracle_finalized = true;
+        assert!(check_settleable(&duel, 51, 1_000).is_ok());
+    }
+    
+    #[test]
+    fn oracle_updates_count_across_oracles() {
+        let mut duel = test_duel();
+        duel.required_oracle_updates = 3;
+        duel.oracle_update_counts = vec![OracleUpdateCount { oracle: Pubkey::new_unique(), count: 2 }];
+        assert_eq!(
+            check_oracle_updates(&duel).unwrap_err(),
+            duel_error(DuelError::InsufficientOracleUpdates)
+        );
+        
+        duel.oracle_update_counts.push(OracleUpdateCount { oracle: Pubkey::new_unique(), count: 1 });
+        assert!(check_oracle_updates(&duel).is_ok());
+    }
+    
+    #[test]
+    fn expired_time_based_duels_short_of_updates_settle_as_draw() {
+        let mut duel = test_duel();
+        duel.end_time = 100;
+        duel.required_oracle_updates = 3;
+        
+        assert!(check_settleable(&duel, 100, 1_000).is_ok());
+        assert!(expired_settlement_winner(&duel, None) == Some(DuelWinner::Draw));
+        
+        duel.oracle_update_counts = vec![OracleUpdateCount { oracle: Pubkey::new_unique(), count: 3 }];
+        assert!(expired_settlement_winner(&duel, None).is_none());
+    }
+    
+    #[test]
+    fn capped_manual_duels_short_of_updates_settle_as_draw() {
+        let mut duel = test_duel();
+        duel.expiry_mode = ExpiryMode::ManualOracle;
+        duel.start_time = 50;
+        duel.required_oracle_updates = 3;
+        
+        // The oracle never reported, yet the duel can still settle past the cap
+        assert!(check_settleable(&duel, 1_050, 1_000).is_ok());
+        assert!(expired_settlement_winner(&duel, None) == Some(DuelWinner::Draw));
+        
+        // An authority-chosen winner is never overridden
+        assert!(
+            expired_settlement_winner(&duel, Some(DuelWinner::Opponent)) == Some(DuelWinner::Opponent)
+        );
+    }
+    
+    #[test]
+    fn first_to_target_settles_early_without_required_updates() {
+        let mut duel = test_duel();
+        duel.win_condition = WinCondition::FirstToTarget { target_pnl_bps: 1_000 };
+        duel.creator_starting_value = 1_000;
+        duel.opponent_starting_value = 1_000;
+        duel.required_oracle_updates = 24;
+        
+        assert_eq!(early_settlement_pnl(&duel, 1_099, 1_000).unwrap(), None);
+        assert_eq!(early_settlement_pnl(&duel, 1_000, 1_100).unwrap(), Some(1_000));
+        
+        duel.win_condition = WinCondition::HighestPnl;
+        assert_eq!(early_settlement_pnl(&duel, 2_000, 1_000).unwrap(), None);
+    }
+    
+    #[test]
+    fn ranked_duels_leave_other_accepted_duels_counted() {
+        let mut protocol = test_protocol();
+        track_duel_status(&mut protocol, None, DuelStatus::Accepted); // Awaiting a deposit
+        
+        let mut ranked = test_duel();
+        ranked.creator_stake_deposited = true;
+        ranked.opponent_stake_deposited = true;
+        activate_ranked_duel(&mut ranked, &mut protocol, 100).unwrap();
+        
+        assert!(ranked.status == DuelStatus::PendingOracle);
+        assert_eq!(protocol.accepted_duels, 1);
+        assert_eq!(protocol.total_active_stake, 2 * ranked.stake_amount);
+    }
+    
+    #[test]
+    fn settle_and_close_returns_record_rent() {
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [3_000_000u64, 1_500_000, 2_000_000]; // Duel, fee, insight
+        let mut data = [Vec::new(), Vec::new(), Vec::new()];
+        let accounts: Vec<AccountInfo> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, true, lamports, data, &crate::ID, false, 0)
+            })
+            .collect();
+        
+        assert_eq!(closed_rent(&accounts).unwrap(), 6_500_000);
+        assert_eq!(closed_rent(&accounts[..1]).unwrap(), 3_000_000);
+        
+        let mut max = u64::MAX;
+        let mut empty = Vec::new();
+        let full = AccountInfo::new(&keys[0], false, true, &mut max, &mut empty, &crate::ID, false, 0);
+        assert_eq!(
+            closed_rent(&[accounts[0].clone(), full]).unwrap_err(),
+            duel_error(DuelError::Overflow)
+        );
+    }
+    
+    #[test]
+    fn loyalty_discount_follows_the_duel_not_the_settler() {
+        let mut data = vec![0; PlayerProfile::space()];
+        data[..8].copy_from_slice(PlayerProfile::DISCRIMINATOR);
+        let mut profile = PlayerProfile::try_deserialize(&mut &data[..]).unwrap();
+        profile.duels_participated = 9;
+        assert!(!is_loyal(Some(&profile), 10));
+        profile.duels_participated = 10;
+        assert!(is_loyal(Some(&profile), 10));
+        assert!(!is_loyal(None, 10));
+        
+        let mut duel = test_duel();
+        duel.creator_loyal = true;
+        assert_eq!(loyal_winner(&duel, DuelWinner::Creator), Some(duel.creator));
+        assert_eq!(loyal_winner(&duel, DuelWinner::Opponent), None);
+        assert_eq!(loyal_winner(&duel, DuelWinner::Draw), None);
+        
+        duel.opponent_loyal = true;
+        assert_eq!(loyal_winner(&duel, DuelWinner::Opponent), Some(duel.opponent));
     }
     
-    ((final_value as i64 - starting_value as i64) * 10000) / starting_value as i64
-}   
\ No newline at end of file
+    #[test]
+    fn price_index_is_editable_by_creator_or_oracle_until_active() {
+        let mut protocol = test_protocol();
+        let oracle = Pubkey::new_unique();
+        protocol.authorized_oracles = vec![oracle];
+        
+        let mut duel = test_duel();
+        assert!(can_edit_price_index(&duel, &protocol, &duel.creator));
+        assert!(can_edit_price_index(&duel, &protocol, &oracle));
+        assert!(!can_edit_price_index(&duel, &protocol, &duel.opponent));
+        
+        assert!(check_price_index_editable(&duel).is_ok());
+        duel.status = DuelStatus::PendingOracle;
+        assert!(check_price_index_editable(&duel).is_ok());
+        duel.status = DuelStatus::Active;
+        assert_eq!(
+            check_price_index_editable(&duel).unwrap_err(),
+            duel_error(DuelError::InvalidStatus)
+        );
+    }
+    
+    #[test]
+    fn insight_return_uses_checked_math() {
+        assert_eq!(return_pct(1_950_000, 1_000_000).unwrap(), 95); // Winner after a 2.5% fee
+        assert_eq!(return_pct(975_000, 1_000_000).unwrap(), -2); // Draw refund
+        assert_eq!(return_pct(u64::MAX, u64::MAX / 2).unwrap(), 100);
+        assert_eq!(return_pct(u64::MAX, 1).unwrap_err(), duel_error(DuelError::Overflow));
+        assert_eq!(return_pct(1, 0).unwrap_err(), duel_error(DuelError::Overflow));
+    }
+    
+    #[test]
+    fn draws_with_an_odd_fee_pay_out_exactly_the_escrow() {
+        // 2 * 1_000_100 * 250 / 10000 = 50_005 is odd, so a draw charges
+        // 50_004 and each side gets an equal refund
+        let stake = 1_000_100;
+        let (fee, refund) = settlement_split(stake, 250, true).unwrap();
+        assert_eq!(fee, 50_004);
+        assert_eq!(fee + 2 * refund, 2 * stake);
+        
+        let (fee, payout) = settlement_split(stake, 250, false).unwrap();
+        assert_eq!(fee, 50_005);
+        assert_eq!(fee + payout, 2 * stake);
+    }
+    
+    #[test]
+    fn duel_account_data_is_validated() {
+        let duel_data = |len: usize| {
+            let mut data = vec![0; len];
+            data[..8].copy_from_slice(Duel::DISCRIMINATOR);
+            data
+        };
+        assert!(check_data(duel_data(MIN_DUEL_SIZE)).is_ok());
+        assert!(check_data(duel_data(MAX_DUEL_SIZE)).is_ok());
+        
+        assert_eq!(
+            check_data(duel_data(MIN_DUEL_SIZE - 1)).unwrap_err(),
+            duel_error(DuelError::AccountDataLengthMismatch)
+        );
+        assert_eq!(
+            check_data(duel_data(MAX_DUEL_SIZE + 1)).unwrap_err(),
+            duel_error(DuelError::AccountDataLengthMismatch)
+        );
+        
+        // A Duel-sized account of another type
+        let mut other = vec![0; MIN_DUEL_SIZE];
+        other[..8].copy_from_slice(Protocol::DISCRIMINATOR);
+        assert_eq!(
+            check_data(other).unwrap_err(),
+            duel_error(DuelError::WrongAccountType)
+        );
+    }
+    
+    #[test]
+    fn verify_zero_init_invariant() {
+        assert!(DuelStatus::default() == DuelStatus::Pending);
+        assert!(DuelWinner::default() == DuelWinner::None);
+        assert!(Duel::default().status == DuelStatus::Pending);
+        assert!(Duel::default().winner == DuelWinner::None);
+        
+        // A freshly allocated account is all zeroes after the discriminator
+        let mut data = vec![0; Duel::space()];
+        data[..8].copy_from_slice(Duel::DISCRIMINATOR);
+        let duel = Duel::try_deserialize(&mut &data[..]).unwrap();
+        assert!(duel.status == DuelStatus::Pending);
+        assert!(duel.winner == DuelWinner::None);
+    }
+    
+    #[test]
+    fn allowed_tokens_normalize_regardless_of_order() {
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        
+        let normalized = normalize_allowed_tokens(vec![a, b, c]);
+        assert_eq!(normalize_allowed_tokens(vec![c, a, b]), normalized);
+        assert_eq!(normalize_allowed_tokens(vec![b, c, a, c, b]), normalized);
+        assert!(normalized.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+    
+    #[test]
+    fn settle_estimate_adds_each_path_cost() {
+        assert_eq!(settle_compute_estimate(false, false, false), BASE_SETTLE_CU);
+        assert_eq!(settle_compute_estimate(true, false, false), BASE_SETTLE_CU + DRAW_EXTRA_CU);
+        assert_eq!(
+            settle_compute_estimate(false, true, false),
+            BASE_SETTLE_CU + WSOL_UNWRAP_EXTRA_CU
+        );
+        assert_eq!(
+            settle_compute_estimate(false, false, true),
+            BASE_SETTLE_CU + CATEGORY_LEADERBOARD_EXTRA_CU
+        );
+        assert_eq!(
+            settle_compute_estimate(true, true, true),
+            BASE_SETTLE_CU + DRAW_EXTRA_CU + WSOL_UNWRAP_EXTRA_CU + CATEGORY_LEADERBOARD_EXTRA_CU
+        );
+    }
+    
+    // Runtime compute limits: the default per-instruction budget, and the
+    // most a transaction can request through ComputeBudgetProgram
+    const DEFAULT_INSTRUCTION_CU_LIMIT: u64 = 200_000;
+    const MAX_TRANSACTION_CU_LIMIT: u64 = 1_400_000;
+    
+    #[test]
+    fn settle_stays_within_compute_budget() {
+        // Any settle_duel path fits the default budget without a ComputeBudget request
+        assert!(settle_compute_estimate(true, true, true) <= DEFAULT_INSTRUCTION_CU_LIMIT);
+        
+        // bulk_settle rejects WSOL duels, so its worst case is a full batch of draws
+        let bulk_worst_case = MAX_BULK_SETTLE as u64 * settle_compute_estimate(true, false, true);
+        assert!(bulk_worst_case <= MAX_TRANSACTION_CU_LIMIT);
+    }
+    
+    #[test]
+    fn pnl_is_in_basis_points() {
+        assert_eq!(safe_calculate_pnl(1_000, 1_500).unwrap(), 5_000);
+        assert_eq!(safe_calculate_pnl(1_000, 500).unwrap(), -5_000);
+        assert_eq!(safe_calculate_pnl(1_000, 0).unwrap(), -10_000);
+        assert_eq!(safe_calculate_pnl(u64::MAX, u64::MAX).unwrap(), 0);
+    }
+    
+    #[test]
+    fn pnl_without_starting_value_is_zero() {
+        assert_eq!(unsafe_calculate_pnl(0, u64::MAX), Some(0));
+        assert_eq!(safe_calculate_pnl(0, u64::MAX).unwrap(), 0);
+    }
+    
+    #[test]
+    fn pnl_overflow_fails_instead_of_wrapping() {
+        assert_eq!(unsafe_calculate_pnl(1, u64::MAX), None);
+        assert_eq!(
+            safe_calculate_pnl(1, u64::MAX).unwrap_err(),
+            duel_error(DuelError::PnlCalculationFailed)
+        );
+        // The largest gain that still fits in i64
+        let max_final = (i64::MAX as u64) / 10_000 + 1;
+        assert_eq!(safe_calculate_pnl(1, max_final).unwrap(), (max_final as i64 - 1) * 10_000);
+    }
+}
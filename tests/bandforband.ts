@@ -1,8 +1,15 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { TradingDuelProtocol } from "../target/types/trading_duel_protocol";
-import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL, SYSVAR_INSTRUCTIONS_PUBKEY } from "@solana/web3.js";
 import { assert, expect } from "chai";
+import {
+  duelArgs,
+  findDuelPda,
+  initializeOracleTracking,
+  newNonce,
+  signedPositionUpdate,
+} from "./helpers";
 
 describe("trading-duel-protocol", () => {
   // Configure the client
@@ -17,6 +24,7 @@ describe("trading-duel-protocol", () => {
   let creator: Keypair;
   let opponent: Keypair;
   let oracle: Keypair;
+  let attestor: Keypair;
   let duelPDA: PublicKey;
   let duelEscrow: PublicKey;
 
@@ -24,6 +32,7 @@ describe("trading-duel-protocol", () => {
   const PROTOCOL_FEE_BPS = 250; // 2.5%
   const STAKE_AMOUNT = 1 * LAMPORTS_PER_SOL; // 1 SOL
   const DUEL_DURATION = 3600; // 1 hour in seconds
+  const TOKEN = Keypair.generate().publicKey; // Protocol default token, traded in position updates
 
   before(async () => {
     // Generate keypairs
@@ -31,6 +40,7 @@ describe("trading-duel-protocol", () => {
     creator = Keypair.generate();
     opponent = Keypair.generate();
     oracle = Keypair.generate();
+    attestor = Keypair.generate();
 
     // Airdrop SOL to test accounts
     const airdropAmount = 10 * LAMPORTS_PER_SOL;
@@ -93,19 +103,40 @@ describe("trading-duel-protocol", () => {
         assert.include(err.toString(), "already in use");
       }
     });
+
+    it("Registers the oracle, trade attestor and default token", async () => {
+      await program.methods
+        .setAuthorizedOracles([oracle.publicKey])
+        .accounts({ protocol: protocolPDA, authority: provider.wallet.publicKey })
+        .rpc();
+      await program.methods
+        .setTradeAttestors([attestor.publicKey])
+        .accounts({ protocol: protocolPDA, authority: provider.wallet.publicKey })
+        .rpc();
+      await program.methods
+        .setDefaultAllowedTokens([TOKEN])
+        .accounts({ protocol: protocolPDA, authority: provider.wallet.publicKey })
+        .rpc();
+
+      const protocolAccount = await program.account.protocol.fetch(protocolPDA);
+      assert.deepEqual(
+        protocolAccount.authorizedOracles.map((key) => key.toString()),
+        [oracle.publicKey.toString()]
+      );
+      assert.deepEqual(
+        protocolAccount.tradeAttestors.map((key) => key.toString()),
+        [attestor.publicKey.toString()]
+      );
+    });
   });
 
   describe("Duel Creation", () => {
+    let duelNonce: anchor.BN;
+
     before(async () => {
-      // Get current protocol state to get the duel index
-      const protocolAccount = await program.account.protocol.fetch(protocolPDA);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-      
-      // Derive duel PDA using correct seeds
-      [duelPDA] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      // Duel PDAs are seeded by the creator and a random nonce
+      duelNonce = newNonce();
+      duelPDA = findDuelPda(program.programId, creator.publicKey, duelNonce);
       
       // Derive escrow PDA
       [duelEscrow] = PublicKey.findProgramAddressSync(
@@ -115,18 +146,21 @@ describe("trading-duel-protocol", () => {
     });
 
     it("Creates a new duel", async () => {
-      const allowedTokens = []; // Empty means all tokens allowed
+      const allowedTokens = []; // Empty falls back to the protocol default tokens
 
       const tx = await program.methods
         .createDuel(
+          duelNonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(DUEL_DURATION),
-          allowedTokens
+          allowedTokens,
+          duelArgs()
         )
         .accounts({
           duel: duelPDA,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -150,25 +184,23 @@ describe("trading-duel-protocol", () => {
     });
 
     it("Fails to create duel with invalid parameters", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPDA);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-      
-      const [invalidDuelPDA] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const invalidDuelPDA = findDuelPda(program.programId, creator.publicKey, nonce);
 
       try {
         await program.methods
           .createDuel(
+            nonce,
             new anchor.BN(0), // Invalid stake amount
             new anchor.BN(DUEL_DURATION),
-            []
+            [],
+            duelArgs()
           )
           .accounts({
             duel: invalidDuelPDA,
             protocol: protocolPDA,
             creator: creator.publicKey,
+            treasury: treasury.publicKey,
             systemProgram: SystemProgram.programId,
           })
           .signers([creator])
@@ -265,8 +297,14 @@ describe("trading-duel-protocol", () => {
         .signers([opponent])
         .rpc();
 
+      // Funded duels wait for the oracle's starting values
+      let duelAccount = await program.account.duel.fetch(duelPDA);
+      assert.equal(duelAccount.status.pendingOracle !== undefined, true);
+
+      await initializeOracleTracking(program, duelPDA, oracle);
+
       // Verify duel is now active
-      const duelAccount = await program.account.duel.fetch(duelPDA);
+      duelAccount = await program.account.duel.fetch(duelPDA);
       assert.equal(duelAccount.opponentStakeDeposited, true);
       assert.equal(duelAccount.status.active !== undefined, true);
       assert.isAbove(duelAccount.startTime.toNumber(), 0);
@@ -302,12 +340,15 @@ describe("trading-duel-protocol", () => {
       const creatorValue = new anchor.BN(1.2 * LAMPORTS_PER_SOL); // 20% profit
       const opponentValue = new anchor.BN(0.9 * LAMPORTS_PER_SOL); // 10% loss
 
+      const update = signedPositionUpdate(duelPDA, oracle, attestor, creatorValue, opponentValue, TOKEN);
       const tx = await program.methods
-        .updatePositions(creatorValue, opponentValue)
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
           duel: duelPDA,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -319,40 +360,45 @@ describe("trading-duel-protocol", () => {
 
     it("Fails to update positions on inactive duel", async () => {
       // Create a new duel for this test
-      const protocolAccount = await program.account.protocol.fetch(protocolPDA);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-      
-      const [inactiveDuelPDA] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const inactiveDuelPDA = findDuelPda(program.programId, creator.publicKey, nonce);
       
       // Create but don't activate a duel
       await program.methods
         .createDuel(
+          nonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(DUEL_DURATION),
-          []
+          [],
+          duelArgs()
         )
         .accounts({
           duel: inactiveDuelPDA,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
         .rpc();
 
       try {
+        const update = signedPositionUpdate(
+          inactiveDuelPDA,
+          oracle,
+          attestor,
+          new anchor.BN(STAKE_AMOUNT),
+          new anchor.BN(STAKE_AMOUNT),
+          TOKEN
+        );
         await program.methods
-          .updatePositions(
-            new anchor.BN(STAKE_AMOUNT),
-            new anchor.BN(STAKE_AMOUNT)
-          )
+          .updatePositions(update.creatorValue, update.opponentValue, update.args)
           .accounts({
             duel: inactiveDuelPDA,
             oracle: oracle.publicKey,
+            instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
           })
+          .preInstructions(update.preInstructions)
           .signers([oracle])
           .rpc();
         
@@ -366,33 +412,37 @@ describe("trading-duel-protocol", () => {
   describe("Duel Settlement", () => {
     it("Settles duel after expiry", async () => {
       // Fast forward time (in tests, we can't actually do this, so we'll create a new duel with 0 duration)
-      const expiredDuel = Keypair.generate();
+      const expiredDuelNonce = newNonce();
+      const expiredDuel = findDuelPda(program.programId, creator.publicKey, expiredDuelNonce);
       const [expiredEscrow] = PublicKey.findProgramAddressSync(
-        [Buffer.from("escrow"), expiredDuel.publicKey.toBuffer()],
+        [Buffer.from("escrow"), expiredDuel.toBuffer()],
         program.programId
       );
 
       // Create and immediately activate a duel with 0 duration
       await program.methods
         .createDuel(
+          expiredDuelNonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(0), // 0 duration
-          []
+          [],
+          duelArgs()
         )
         .accounts({
-          duel: expiredDuel.publicKey,
+          duel: expiredDuel,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
-        .signers([creator, expiredDuel])
+        .signers([creator])
         .rpc();
 
       // Accept
       await program.methods
         .acceptDuel()
         .accounts({
-          duel: expiredDuel.publicKey,
+          duel: expiredDuel,
           opponent: opponent.publicKey,
         })
         .signers([opponent])
@@ -402,7 +452,7 @@ describe("trading-duel-protocol", () => {
       await program.methods
         .depositStake()
         .accounts({
-          duel: expiredDuel.publicKey,
+          duel: expiredDuel,
           duelEscrow: expiredEscrow,
           depositor: creator.publicKey,
           systemProgram: SystemProgram.programId,
@@ -413,7 +463,7 @@ describe("trading-duel-protocol", () => {
       await program.methods
         .depositStake()
         .accounts({
-          duel: expiredDuel.publicKey,
+          duel: expiredDuel,
           duelEscrow: expiredEscrow,
           depositor: opponent.publicKey,
           systemProgram: SystemProgram.programId,
@@ -421,16 +471,25 @@ describe("trading-duel-protocol", () => {
         .signers([opponent])
         .rpc();
 
+      await initializeOracleTracking(program, expiredDuel, oracle);
+
       // Update positions
+      const update = signedPositionUpdate(
+        expiredDuel,
+        oracle,
+        attestor,
+        new anchor.BN(1.5 * LAMPORTS_PER_SOL), // Creator wins
+        new anchor.BN(0.8 * LAMPORTS_PER_SOL), // Opponent loses
+        TOKEN
+      );
       await program.methods
-        .updatePositions(
-          new anchor.BN(1.5 * LAMPORTS_PER_SOL), // Creator wins
-          new anchor.BN(0.8 * LAMPORTS_PER_SOL)  // Opponent loses
-        )
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
-          duel: expiredDuel.publicKey,
+          duel: expiredDuel,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -442,7 +501,7 @@ describe("trading-duel-protocol", () => {
       await program.methods
         .settleDuel()
         .accounts({
-          duel: expiredDuel.publicKey,
+          duel: expiredDuel,
           protocol: protocolPDA,
           duelEscrow: expiredEscrow,
           creator: creator.publicKey,
@@ -452,7 +511,7 @@ describe("trading-duel-protocol", () => {
         .rpc();
 
       // Verify settlement
-      const duelAccount = await program.account.duel.fetch(expiredDuel.publicKey);
+      const duelAccount = await program.account.duel.fetch(expiredDuel);
       assert.equal(duelAccount.status.settled !== undefined, true);
       assert.equal(duelAccount.winner.creator !== undefined, true);
 
@@ -473,32 +532,36 @@ describe("trading-duel-protocol", () => {
     });
 
     it("Handles draw correctly", async () => {
-      const drawDuel = Keypair.generate();
+      const drawDuelNonce = newNonce();
+      const drawDuel = findDuelPda(program.programId, creator.publicKey, drawDuelNonce);
       const [drawEscrow] = PublicKey.findProgramAddressSync(
-        [Buffer.from("escrow"), drawDuel.publicKey.toBuffer()],
+        [Buffer.from("escrow"), drawDuel.toBuffer()],
         program.programId
       );
 
       // Create, accept, and activate duel
       await program.methods
         .createDuel(
+          drawDuelNonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(0),
-          []
+          [],
+          duelArgs()
         )
         .accounts({
-          duel: drawDuel.publicKey,
+          duel: drawDuel,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
-        .signers([creator, drawDuel])
+        .signers([creator])
         .rpc();
 
       await program.methods
         .acceptDuel()
         .accounts({
-          duel: drawDuel.publicKey,
+          duel: drawDuel,
           opponent: opponent.publicKey,
         })
         .signers([opponent])
@@ -507,7 +570,7 @@ describe("trading-duel-protocol", () => {
       await program.methods
         .depositStake()
         .accounts({
-          duel: drawDuel.publicKey,
+          duel: drawDuel,
           duelEscrow: drawEscrow,
           depositor: creator.publicKey,
           systemProgram: SystemProgram.programId,
@@ -518,7 +581,7 @@ describe("trading-duel-protocol", () => {
       await program.methods
         .depositStake()
         .accounts({
-          duel: drawDuel.publicKey,
+          duel: drawDuel,
           duelEscrow: drawEscrow,
           depositor: opponent.publicKey,
           systemProgram: SystemProgram.programId,
@@ -526,16 +589,25 @@ describe("trading-duel-protocol", () => {
         .signers([opponent])
         .rpc();
 
+      await initializeOracleTracking(program, drawDuel, oracle);
+
       // Update with equal positions
+      const update = signedPositionUpdate(
+        drawDuel,
+        oracle,
+        attestor,
+        new anchor.BN(STAKE_AMOUNT),
+        new anchor.BN(STAKE_AMOUNT),
+        TOKEN
+      );
       await program.methods
-        .updatePositions(
-          new anchor.BN(STAKE_AMOUNT),
-          new anchor.BN(STAKE_AMOUNT)
-        )
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
-          duel: drawDuel.publicKey,
+          duel: drawDuel,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -547,7 +619,7 @@ describe("trading-duel-protocol", () => {
       await program.methods
         .settleDuel()
         .accounts({
-          duel: drawDuel.publicKey,
+          duel: drawDuel,
           protocol: protocolPDA,
           duelEscrow: drawEscrow,
           creator: creator.publicKey,
@@ -557,7 +629,7 @@ describe("trading-duel-protocol", () => {
         .rpc();
 
       // Verify draw
-      const duelAccount = await program.account.duel.fetch(drawDuel.publicKey);
+      const duelAccount = await program.account.duel.fetch(drawDuel);
       assert.equal(duelAccount.winner.draw !== undefined, true);
 
       // Both should receive stake minus half fee each
@@ -582,59 +654,67 @@ describe("trading-duel-protocol", () => {
 
   describe("Duel Cancellation", () => {
     it("Creator can cancel pending duel", async () => {
-      const cancelDuel = Keypair.generate();
+      const cancelDuelNonce = newNonce();
+      const cancelDuel = findDuelPda(program.programId, creator.publicKey, cancelDuelNonce);
 
       await program.methods
         .createDuel(
+          cancelDuelNonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(DUEL_DURATION),
-          []
+          [],
+          duelArgs()
         )
         .accounts({
-          duel: cancelDuel.publicKey,
+          duel: cancelDuel,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
-        .signers([creator, cancelDuel])
+        .signers([creator])
         .rpc();
 
       await program.methods
         .cancelDuel()
         .accounts({
-          duel: cancelDuel.publicKey,
+          duel: cancelDuel,
           creator: creator.publicKey,
         })
         .signers([creator])
         .rpc();
 
-      const duelAccount = await program.account.duel.fetch(cancelDuel.publicKey);
+      const duelAccount = await program.account.duel.fetch(cancelDuel);
       assert.equal(duelAccount.status.cancelled !== undefined, true);
     });
 
     it("Non-creator cannot cancel duel", async () => {
-      const anotherDuel = Keypair.generate();
+      const anotherDuelNonce = newNonce();
+      const anotherDuel = findDuelPda(program.programId, creator.publicKey, anotherDuelNonce);
 
       await program.methods
         .createDuel(
+          anotherDuelNonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(DUEL_DURATION),
-          []
+          [],
+          duelArgs()
         )
         .accounts({
-          duel: anotherDuel.publicKey,
+          duel: anotherDuel,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
-        .signers([creator, anotherDuel])
+        .signers([creator])
         .rpc();
 
       try {
         await program.methods
           .cancelDuel()
           .accounts({
-            duel: anotherDuel.publicKey,
+            duel: anotherDuel,
             creator: opponent.publicKey,
           })
           .signers([opponent])
@@ -647,27 +727,31 @@ describe("trading-duel-protocol", () => {
     });
 
     it("Cannot cancel accepted duel", async () => {
-      const acceptedDuel = Keypair.generate();
+      const acceptedDuelNonce = newNonce();
+      const acceptedDuel = findDuelPda(program.programId, creator.publicKey, acceptedDuelNonce);
 
       await program.methods
         .createDuel(
+          acceptedDuelNonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(DUEL_DURATION),
-          []
+          [],
+          duelArgs()
         )
         .accounts({
-          duel: acceptedDuel.publicKey,
+          duel: acceptedDuel,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
-        .signers([creator, acceptedDuel])
+        .signers([creator])
         .rpc();
 
       await program.methods
         .acceptDuel()
         .accounts({
-          duel: acceptedDuel.publicKey,
+          duel: acceptedDuel,
           opponent: opponent.publicKey,
         })
         .signers([opponent])
@@ -677,7 +761,7 @@ describe("trading-duel-protocol", () => {
         await program.methods
           .cancelDuel()
           .accounts({
-            duel: acceptedDuel.publicKey,
+            duel: acceptedDuel,
             creator: creator.publicKey,
           })
           .signers([creator])
@@ -692,25 +776,29 @@ describe("trading-duel-protocol", () => {
 
   describe("Edge Cases", () => {
     it("Handles maximum allowed tokens", async () => {
-      const maxTokensDuel = Keypair.generate();
+      const maxTokensDuelNonce = newNonce();
+      const maxTokensDuel = findDuelPda(program.programId, creator.publicKey, maxTokensDuelNonce);
       const allowedTokens = Array(10).fill(null).map(() => Keypair.generate().publicKey);
 
       await program.methods
         .createDuel(
+          maxTokensDuelNonce,
           new anchor.BN(STAKE_AMOUNT),
           new anchor.BN(DUEL_DURATION),
-          allowedTokens
+          allowedTokens,
+          duelArgs()
         )
         .accounts({
-          duel: maxTokensDuel.publicKey,
+          duel: maxTokensDuel,
           protocol: protocolPDA,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
-        .signers([creator, maxTokensDuel])
+        .signers([creator])
         .rpc();
 
-      const duelAccount = await program.account.duel.fetch(maxTokensDuel.publicKey);
+      const duelAccount = await program.account.duel.fetch(maxTokensDuel);
       assert.equal(duelAccount.allowedTokens.length, 10);
     });
 
@@ -1,8 +1,15 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { TradingDuelProtocol } from "../target/types/trading_duel_protocol";
-import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL, SYSVAR_INSTRUCTIONS_PUBKEY } from "@solana/web3.js";
 import { expect } from "chai";
+import {
+  duelArgs,
+  findDuelPda,
+  initializeOracleTracking,
+  newNonce,
+  signedPositionUpdate,
+} from "./helpers";
 
 describe("Trading Duel Protocol - Comprehensive Tests", () => {
   const provider = anchor.AnchorProvider.env();
@@ -15,6 +22,7 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
   let creator: Keypair;
   let opponent: Keypair;
   let oracle: Keypair;
+  let attestor: Keypair;
   
   // PDAs
   let protocolPda: PublicKey;
@@ -24,6 +32,7 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
   const PROTOCOL_FEE_BPS = 250; // 2.5%
   const STAKE_AMOUNT = new anchor.BN(1 * LAMPORTS_PER_SOL);
   const DURATION = new anchor.BN(3600); // 1 hour
+  const TOKEN = Keypair.generate().publicKey; // Protocol default token, traded in position updates
 
   before(async () => {
     // Initialize keypairs
@@ -32,6 +41,7 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     creator = Keypair.generate();
     opponent = Keypair.generate();
     oracle = Keypair.generate();
+    attestor = Keypair.generate();
 
     // Airdrop SOL
     await Promise.all([
@@ -70,6 +80,32 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
       expect(protocolAccount.totalVolume.toNumber()).to.equal(0);
     });
 
+    it("Should register the oracle, trade attestor and default token", async () => {
+      await program.methods
+        .setAuthorizedOracles([oracle.publicKey])
+        .accounts({ protocol: protocolPda, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      await program.methods
+        .setTradeAttestors([attestor.publicKey])
+        .accounts({ protocol: protocolPda, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      await program.methods
+        .setDefaultAllowedTokens([TOKEN])
+        .accounts({ protocol: protocolPda, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+      const protocolAccount = await program.account.protocol.fetch(protocolPda);
+      expect(protocolAccount.authorizedOracles.map((key) => key.toString())).to.deep.equal([
+        oracle.publicKey.toString(),
+      ]);
+      expect(protocolAccount.tradeAttestors.map((key) => key.toString())).to.deep.equal([
+        attestor.publicKey.toString(),
+      ]);
+    });
+
     it("Should fail to reinitialize protocol", async () => {
       try {
         await program.methods
@@ -94,13 +130,8 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     let escrowPda: PublicKey;
 
     it("Should create a duel successfully", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [duelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      duelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       [escrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), duelPda.toBuffer()],
@@ -108,10 +139,11 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
       );
 
       await program.methods
-        .createDuel(STAKE_AMOUNT, DURATION, [])
+        .createDuel(nonce, STAKE_AMOUNT, DURATION, [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -130,20 +162,16 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     });
 
     it("Should fail with zero stake amount", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [errorDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const errorDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       try {
         await program.methods
-          .createDuel(new anchor.BN(0), DURATION, [])
+          .createDuel(nonce, new anchor.BN(0), DURATION, [], duelArgs())
           .accounts({
             protocol: protocolPda,
             creator: creator.publicKey,
+            treasury: treasury.publicKey,
             systemProgram: SystemProgram.programId,
           })
           .signers([creator])
@@ -162,27 +190,26 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         Keypair.generate().publicKey,
       ];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [tokenDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const tokenDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(STAKE_AMOUNT, DURATION, allowedTokens)
+        .createDuel(nonce, STAKE_AMOUNT, DURATION, allowedTokens, duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
         .rpc();
 
       const duelAccount = await program.account.duel.fetch(tokenDuelPda);
-      expect(duelAccount.allowedTokens.length).to.equal(3);
-      expect(duelAccount.allowedTokens[0].toString()).to.equal(allowedTokens[0].toString());
+      // Stored sorted by key bytes
+      const sortedTokens = [...allowedTokens].sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()));
+      expect(duelAccount.allowedTokens.map((key) => key.toString())).to.deep.equal(
+        sortedTokens.map((key) => key.toString())
+      );
     });
 
     describe("Duel Acceptance", () => {
@@ -263,7 +290,13 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
           .signers([opponent])
           .rpc();
 
-        const duelAccount = await program.account.duel.fetch(duelPda);
+        // Funded duels wait for the oracle's starting values
+        let duelAccount = await program.account.duel.fetch(duelPda);
+        expect(duelAccount.status).to.deep.equal({ pendingOracle: {} });
+
+        await initializeOracleTracking(program, duelPda, oracle);
+
+        duelAccount = await program.account.duel.fetch(duelPda);
         expect(duelAccount.opponentStakeDeposited).to.be.true;
         expect(duelAccount.status).to.deep.equal({ active: {} });
         expect(duelAccount.startTime.toNumber()).to.be.greaterThan(0);
@@ -281,13 +314,8 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
       it("Should fail to deposit stake from non-participant", async () => {
         // Create a new duel for this test
-        const protocolAccount = await program.account.protocol.fetch(protocolPda);
-        const duelIndex = protocolAccount.totalDuels.toNumber();
-
-        const [testDuelPda] = PublicKey.findProgramAddressSync(
-          [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-          program.programId
-        );
+        const nonce = newNonce();
+        const testDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
         const [testEscrowPda] = PublicKey.findProgramAddressSync(
           [Buffer.from("escrow"), testDuelPda.toBuffer()],
@@ -295,10 +323,11 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         );
 
         await program.methods
-          .createDuel(new anchor.BN(0.5 * LAMPORTS_PER_SOL), DURATION, [])
+          .createDuel(nonce, new anchor.BN(0.5 * LAMPORTS_PER_SOL), DURATION, [], duelArgs())
           .accounts({
             protocol: protocolPda,
             creator: creator.publicKey,
+            treasury: treasury.publicKey,
             systemProgram: SystemProgram.programId,
           })
           .signers([creator])
@@ -340,12 +369,15 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         const creatorValue = new anchor.BN(1.2 * LAMPORTS_PER_SOL); // 20% gain
         const opponentValue = new anchor.BN(0.8 * LAMPORTS_PER_SOL); // 20% loss
 
+        const update = signedPositionUpdate(duelPda, oracle, attestor, creatorValue, opponentValue, TOKEN);
         await program.methods
-          .updatePositions(creatorValue, opponentValue)
+          .updatePositions(update.creatorValue, update.opponentValue, update.args)
           .accounts({
             duel: duelPda,
             oracle: oracle.publicKey,
+            instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
           })
+          .preInstructions(update.preInstructions)
           .signers([oracle])
           .rpc();
 
@@ -356,31 +388,37 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
       it("Should fail to update positions on inactive duel", async () => {
         // Create but don't activate a duel
-        const protocolAccount = await program.account.protocol.fetch(protocolPda);
-        const duelIndex = protocolAccount.totalDuels.toNumber();
-
-        const [inactiveDuelPda] = PublicKey.findProgramAddressSync(
-          [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-          program.programId
-        );
+        const nonce = newNonce();
+        const inactiveDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
         await program.methods
-          .createDuel(STAKE_AMOUNT, DURATION, [])
+          .createDuel(nonce, STAKE_AMOUNT, DURATION, [], duelArgs())
           .accounts({
             protocol: protocolPda,
             creator: creator.publicKey,
+            treasury: treasury.publicKey,
             systemProgram: SystemProgram.programId,
           })
           .signers([creator])
           .rpc();
 
         try {
+          const update = signedPositionUpdate(
+            inactiveDuelPda,
+            oracle,
+            attestor,
+            STAKE_AMOUNT,
+            STAKE_AMOUNT,
+            TOKEN
+          );
           await program.methods
-            .updatePositions(STAKE_AMOUNT, STAKE_AMOUNT)
+            .updatePositions(update.creatorValue, update.opponentValue, update.args)
             .accounts({
               duel: inactiveDuelPda,
               oracle: oracle.publicKey,
+              instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
             })
+            .preInstructions(update.preInstructions)
             .signers([oracle])
             .rpc();
           
@@ -394,12 +432,22 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         // This is testing the time constraint logic
         try {
           // Try to update positions far in the future (would fail due to timestamp check)
+          const update = signedPositionUpdate(
+            duelPda,
+            oracle,
+            attestor,
+            new anchor.BN(1.5 * LAMPORTS_PER_SOL),
+            new anchor.BN(0.5 * LAMPORTS_PER_SOL),
+            TOKEN
+          );
           await program.methods
-            .updatePositions(new anchor.BN(1.5 * LAMPORTS_PER_SOL), new anchor.BN(0.5 * LAMPORTS_PER_SOL))
+            .updatePositions(update.creatorValue, update.opponentValue, update.args)
             .accounts({
               duel: duelPda,
               oracle: oracle.publicKey,
+              instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
             })
+            .preInstructions(update.preInstructions)
             .signers([oracle])
             .rpc();
         } catch (error) {
@@ -416,13 +464,8 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
     before(async () => {
       // Create a quick duel for settlement testing
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [settleDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      settleDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       [settleEscrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), settleDuelPda.toBuffer()],
@@ -431,10 +474,11 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
       // Create, accept, and fund a quick duel (0 duration for immediate settlement)
       await program.methods
-        .createDuel(new anchor.BN(0.5 * LAMPORTS_PER_SOL), new anchor.BN(0), [])
+        .createDuel(nonce, new anchor.BN(0.5 * LAMPORTS_PER_SOL), new anchor.BN(0), [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -468,19 +512,28 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         })
         .signers([opponent])
         .rpc();
+
+      await initializeOracleTracking(program, settleDuelPda, oracle);
     });
 
     it("Should settle duel with creator winning", async () => {
       // Update positions - creator wins
+      const update = signedPositionUpdate(
+        settleDuelPda,
+        oracle,
+        attestor,
+        new anchor.BN(0.6 * LAMPORTS_PER_SOL), // Creator gains 20%
+        new anchor.BN(0.4 * LAMPORTS_PER_SOL), // Opponent loses 20%
+        TOKEN
+      );
       await program.methods
-        .updatePositions(
-          new anchor.BN(0.6 * LAMPORTS_PER_SOL), // Creator gains 20%
-          new anchor.BN(0.4 * LAMPORTS_PER_SOL)  // Opponent loses 20%
-        )
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
           duel: settleDuelPda,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -528,13 +581,8 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
     it("Should handle draw correctly", async () => {
       // Create another quick duel for draw testing
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [drawDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const drawDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       const [drawEscrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), drawDuelPda.toBuffer()],
@@ -543,10 +591,11 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
       // Create and fully activate duel
       await program.methods
-        .createDuel(new anchor.BN(0.3 * LAMPORTS_PER_SOL), new anchor.BN(0), [])
+        .createDuel(nonce, new anchor.BN(0.3 * LAMPORTS_PER_SOL), new anchor.BN(0), [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -581,16 +630,25 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         .signers([opponent])
         .rpc();
 
+      await initializeOracleTracking(program, drawDuelPda, oracle);
+
       // Update with equal positions for draw
+      const update = signedPositionUpdate(
+        drawDuelPda,
+        oracle,
+        attestor,
+        new anchor.BN(0.3 * LAMPORTS_PER_SOL),
+        new anchor.BN(0.3 * LAMPORTS_PER_SOL),
+        TOKEN
+      );
       await program.methods
-        .updatePositions(
-          new anchor.BN(0.3 * LAMPORTS_PER_SOL),
-          new anchor.BN(0.3 * LAMPORTS_PER_SOL)
-        )
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
           duel: drawDuelPda,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -635,13 +693,8 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
     it("Should handle opponent winning", async () => {
       // Create another duel for opponent winning test
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [opponentWinDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const opponentWinDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       const [opponentWinEscrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), opponentWinDuelPda.toBuffer()],
@@ -650,10 +703,11 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
       // Create and activate duel
       await program.methods
-        .createDuel(new anchor.BN(0.2 * LAMPORTS_PER_SOL), new anchor.BN(0), [])
+        .createDuel(nonce, new anchor.BN(0.2 * LAMPORTS_PER_SOL), new anchor.BN(0), [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -688,16 +742,25 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         .signers([opponent])
         .rpc();
 
+      await initializeOracleTracking(program, opponentWinDuelPda, oracle);
+
       // Update positions - opponent wins
+      const update = signedPositionUpdate(
+        opponentWinDuelPda,
+        oracle,
+        attestor,
+        new anchor.BN(0.15 * LAMPORTS_PER_SOL), // Creator loses 25%
+        new anchor.BN(0.25 * LAMPORTS_PER_SOL), // Opponent gains 25%
+        TOKEN
+      );
       await program.methods
-        .updatePositions(
-          new anchor.BN(0.15 * LAMPORTS_PER_SOL), // Creator loses 25%
-          new anchor.BN(0.25 * LAMPORTS_PER_SOL)  // Opponent gains 25%
-        )
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
           duel: opponentWinDuelPda,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -733,13 +796,8 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
     it("Should fail to settle non-expired duel", async () => {
       // Create a duel with longer duration
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [longDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const longDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       const [longDuelEscrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), longDuelPda.toBuffer()],
@@ -748,10 +806,11 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
       // Create and start the duel
       await program.methods
-        .createDuel(new anchor.BN(0.1 * LAMPORTS_PER_SOL), new anchor.BN(3600), [])
+        .createDuel(nonce, new anchor.BN(0.1 * LAMPORTS_PER_SOL), new anchor.BN(3600), [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -786,6 +845,8 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
         .signers([opponent])
         .rpc();
 
+      await initializeOracleTracking(program, longDuelPda, oracle);
+
       // Try to settle before expiry
       try {
         await program.methods
@@ -810,19 +871,15 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
 
   describe("Duel Cancellation", () => {
     it("Should allow creator to cancel pending duel", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [cancelDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const cancelDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(STAKE_AMOUNT, DURATION, [])
+        .createDuel(nonce, STAKE_AMOUNT, DURATION, [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -842,19 +899,15 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     });
 
     it("Should fail to cancel accepted duel", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [acceptedDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const acceptedDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(STAKE_AMOUNT, DURATION, [])
+        .createDuel(nonce, STAKE_AMOUNT, DURATION, [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -886,19 +939,15 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     });
 
     it("Should fail when non-creator tries to cancel", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [unauthorizedCancelDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const unauthorizedCancelDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(STAKE_AMOUNT, DURATION, [])
+        .createDuel(nonce, STAKE_AMOUNT, DURATION, [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -925,19 +974,15 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     it("Should handle maximum allowed tokens", async () => {
       const allowedTokens = Array(10).fill(null).map(() => Keypair.generate().publicKey);
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [maxTokensDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const maxTokensDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(STAKE_AMOUNT, DURATION, allowedTokens)
+        .createDuel(nonce, STAKE_AMOUNT, DURATION, allowedTokens, duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -964,20 +1009,16 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     });
 
     it("Should handle minimum stake amounts", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [minStakeDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const minStakeDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       // Test with 1 lamport (minimum possible)
       await program.methods
-        .createDuel(new anchor.BN(1), DURATION, [])
+        .createDuel(nonce, new anchor.BN(1), DURATION, [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -988,20 +1029,16 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     });
 
     it("Should handle very short duration duels", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [shortDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const shortDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       // Test with 1 second duration
       await program.methods
-        .createDuel(new anchor.BN(0.1 * LAMPORTS_PER_SOL), new anchor.BN(1), [])
+        .createDuel(nonce, new anchor.BN(0.1 * LAMPORTS_PER_SOL), new anchor.BN(1), [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -1012,22 +1049,18 @@ describe("Trading Duel Protocol - Comprehensive Tests", () => {
     });
 
     it("Should handle large stake amounts", async () => {
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [largeStakeDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const largeStakeDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       // Test with large stake amount
       const largeStake = new anchor.BN(100 * LAMPORTS_PER_SOL);
       
       await program.methods
-        .createDuel(largeStake, DURATION, [])
+        .createDuel(nonce, largeStake, DURATION, [], duelArgs())
         .accounts({
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
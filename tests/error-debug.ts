@@ -2,6 +2,7 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { TradingDuelProtocol } from "../target/types/trading_duel_protocol";
 import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { duelArgs, findDuelPda, newNonce } from "./helpers";
 
 describe("Error Debug Test", () => {
   const provider = anchor.AnchorProvider.env();
@@ -46,20 +47,17 @@ describe("Error Debug Test", () => {
   });
 
   it("Should test error format", async () => {
-    const protocolAccount = await program.account.protocol.fetch(protocolPda);
-    const duelIndex = protocolAccount.totalDuels.toNumber();
+    const nonce = newNonce();
 
-    const [duelPda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-      program.programId
-    );
+    const duelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
     // Create duel
     await program.methods
-      .createDuel(new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(3600), [])
+      .createDuel(nonce, new anchor.BN(LAMPORTS_PER_SOL), new anchor.BN(3600), [Keypair.generate().publicKey], duelArgs())
       .accounts({
         protocol: protocolPda,
         creator: creator.publicKey,
+        treasury: treasury.publicKey,
         systemProgram: SystemProgram.programId,
       })
       .signers([creator])
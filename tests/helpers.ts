@@ -0,0 +1,116 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Ed25519Program, Keypair, PublicKey, TransactionInstruction } from "@solana/web3.js";
+import { createHash, randomBytes } from "crypto";
+
+// Random nonce for create_duel; duel PDAs are unique per creator + nonce
+export function newNonce(): anchor.BN {
+  return new anchor.BN(randomBytes(8), "le");
+}
+
+export function findDuelPda(programId: PublicKey, creator: PublicKey, nonce: anchor.BN): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("duel"), creator.toBuffer(), nonce.toArrayLike(Buffer, "le", 8)],
+    programId
+  )[0];
+}
+
+// create_duel options for a plain time-based, highest-PnL duel
+export function duelArgs() {
+  return {
+    category: { custom: {} },
+    clientVersion: 1,
+    creatorTokenAllocation: [],
+    winCondition: { highestPnl: {} },
+    useWsol: false,
+    expiryMode: { timeBased: {} },
+    duelUsesSolNatively: false,
+  };
+}
+
+// Ed25519 program instructions store the signature after the 16-byte header
+// and the 32-byte public key
+const ED25519_SIGNATURE_OFFSET = 48;
+
+function signEd25519(signer: Keypair, message: Buffer): { ix: TransactionInstruction; signature: number[] } {
+  const ix = Ed25519Program.createInstructionWithPrivateKey({
+    privateKey: signer.secretKey,
+    message,
+  });
+  const signature = Array.from(ix.data.subarray(ED25519_SIGNATURE_OFFSET, ED25519_SIGNATURE_OFFSET + 64));
+  return { ix, signature };
+}
+
+// update_positions arguments signed by the oracle and a registered trade
+// attestor, plus the two Ed25519 instructions that must precede the update
+export function signedPositionUpdate(
+  duel: PublicKey,
+  oracle: Keypair,
+  attestor: Keypair,
+  creatorValue: anchor.BN,
+  opponentValue: anchor.BN,
+  token: PublicKey,
+  isFinal = false
+) {
+  const timestamp = new anchor.BN(Math.floor(Date.now() / 1000));
+  const tradeAmount = new anchor.BN(1);
+
+  const trade = signEd25519(
+    attestor,
+    Buffer.concat([
+      duel.toBuffer(),
+      token.toBuffer(),
+      tradeAmount.toArrayLike(Buffer, "le", 8),
+      timestamp.toArrayLike(Buffer, "le", 8),
+    ])
+  );
+  const oracleDigest = createHash("sha256")
+    .update(
+      Buffer.concat([
+        duel.toBuffer(),
+        creatorValue.toArrayLike(Buffer, "le", 8),
+        opponentValue.toArrayLike(Buffer, "le", 8),
+        timestamp.toArrayLike(Buffer, "le", 8),
+      ])
+    )
+    .digest();
+  const oracleSignature = signEd25519(oracle, oracleDigest);
+
+  return {
+    creatorValue,
+    opponentValue,
+    args: {
+      creatorAllocation: [],
+      opponentAllocation: [],
+      tradeProof: {
+        tradeSignature: trade.signature,
+        tradePubkey: Array.from(attestor.publicKey.toBytes()),
+        tradeAmount,
+        token,
+        timestamp,
+      },
+      isFinal,
+      oracleSignature: oracleSignature.signature,
+      oracleTimestamp: timestamp,
+    },
+    preInstructions: [oracleSignature.ix, trade.ix],
+  };
+}
+
+// Move a funded duel out of PendingOracle. The oracle starts both sides at
+// the staked amount, so later updates read as PnL on the stake.
+export async function initializeOracleTracking(
+  program: anchor.Program<any>,
+  duel: PublicKey,
+  oracle: Keypair
+) {
+  const { stakeAmount } = await program.account.duel.fetch(duel);
+  await program.methods
+    .initializeOracleTracking(stakeAmount, stakeAmount)
+    .accounts({
+      duel,
+      oracle: oracle.publicKey,
+      duelTimer: null,
+    })
+    .signers([oracle])
+    .rpc();
+}
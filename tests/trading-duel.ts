@@ -1,8 +1,15 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
-import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL, SYSVAR_INSTRUCTIONS_PUBKEY } from "@solana/web3.js";
 import { TradingDuelProtocol } from "../target/types/trading_duel_protocol";
 import { expect } from "chai";
+import {
+  duelArgs,
+  findDuelPda,
+  initializeOracleTracking,
+  newNonce,
+  signedPositionUpdate,
+} from "./helpers";
 
 describe("Trading Duel Protocol", () => {
   // Configure the client to use the local cluster.
@@ -17,12 +24,15 @@ describe("Trading Duel Protocol", () => {
   let creator: Keypair;
   let opponent: Keypair;
   let oracle: Keypair;
+  let attestor: Keypair;
+  
+  // Protocol default token, traded in position updates
+  const TOKEN = Keypair.generate().publicKey;
   
   // PDAs
   let protocolPda: PublicKey;
   let protocolBump: number;
   let duelPda: PublicKey;
-  let duelBump: number;
   let escrowPda: PublicKey;
   let escrowBump: number;
 
@@ -33,6 +43,7 @@ describe("Trading Duel Protocol", () => {
     creator = Keypair.generate();
     opponent = Keypair.generate();
     oracle = Keypair.generate();
+    attestor = Keypair.generate();
 
     // Airdrop SOL to test accounts
     await Promise.all([
@@ -94,6 +105,32 @@ describe("Trading Duel Protocol", () => {
         expect(error.message).to.include("already in use");
       }
     });
+
+    it("Should register the oracle, trade attestor and default token", async () => {
+      await program.methods
+        .setAuthorizedOracles([oracle.publicKey])
+        .accounts({ protocol: protocolPda, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      await program.methods
+        .setTradeAttestors([attestor.publicKey])
+        .accounts({ protocol: protocolPda, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      await program.methods
+        .setDefaultAllowedTokens([TOKEN])
+        .accounts({ protocol: protocolPda, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+      const protocolAccount = await program.account.protocol.fetch(protocolPda);
+      expect(protocolAccount.authorizedOracles.map((key) => key.toString())).to.deep.equal([
+        oracle.publicKey.toString(),
+      ]);
+      expect(protocolAccount.tradeAttestors.map((key) => key.toString())).to.deep.equal([
+        attestor.publicKey.toString(),
+      ]);
+    });
   });
 
   describe("Duel Creation", () => {
@@ -103,20 +140,16 @@ describe("Trading Duel Protocol", () => {
       const allowedTokens: PublicKey[] = [];
 
       // Get current protocol state for duel index
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [duelPda, duelBump] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      duelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: duelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -141,21 +174,17 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(3600);
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [duelPda, duelBump] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      duelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       try {
         await program.methods
-          .createDuel(stakeAmount, duration, allowedTokens)
+          .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
           .accounts({
             duel: duelPda,
             protocol: protocolPda,
             creator: creator.publicKey,
+            treasury: treasury.publicKey,
             systemProgram: SystemProgram.programId,
           })
           .signers([creator])
@@ -177,20 +206,16 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(1800); // 30 minutes
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [testDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      testDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: testDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -245,13 +270,8 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(900); // 15 minutes
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [testDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      testDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       [testEscrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), testDuelPda.toBuffer()],
@@ -260,11 +280,12 @@ describe("Trading Duel Protocol", () => {
 
       // Create duel
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: testDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -321,8 +342,14 @@ describe("Trading Duel Protocol", () => {
         .signers([opponent])
         .rpc();
 
+      // Funded duels wait for the oracle's starting values
+      let duelAccount = await program.account.duel.fetch(testDuelPda);
+      expect(duelAccount.status).to.deep.equal({ pendingOracle: {} });
+
+      await initializeOracleTracking(program, testDuelPda, oracle);
+
       // Verify duel state
-      const duelAccount = await program.account.duel.fetch(testDuelPda);
+      duelAccount = await program.account.duel.fetch(testDuelPda);
       expect(duelAccount.opponentStakeDeposited).to.be.true;
       expect(duelAccount.status).to.deep.equal({ active: {} });
       expect(duelAccount.startTime.toNumber()).to.be.greaterThan(0);
@@ -350,13 +377,8 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(600); // 10 minutes
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [activeDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      activeDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       const [escrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), activeDuelPda.toBuffer()],
@@ -365,11 +387,12 @@ describe("Trading Duel Protocol", () => {
 
       // Create, accept, and fund the duel
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: activeDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -405,18 +428,23 @@ describe("Trading Duel Protocol", () => {
         })
         .signers([opponent])
         .rpc();
+
+      await initializeOracleTracking(program, activeDuelPda, oracle);
     });
 
     it("Should update positions successfully", async () => {
       const creatorValue = new anchor.BN(0.12 * LAMPORTS_PER_SOL); // 20% gain
       const opponentValue = new anchor.BN(0.08 * LAMPORTS_PER_SOL); // 20% loss
 
+      const update = signedPositionUpdate(activeDuelPda, oracle, attestor, creatorValue, opponentValue, TOKEN);
       await program.methods
-        .updatePositions(creatorValue, opponentValue)
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
           duel: activeDuelPda,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -432,32 +460,38 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(600);
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [pendingDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const pendingDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: pendingDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
         .rpc();
 
       try {
+        const update = signedPositionUpdate(
+          pendingDuelPda,
+          oracle,
+          attestor,
+          new anchor.BN(0.1 * LAMPORTS_PER_SOL),
+          new anchor.BN(0.1 * LAMPORTS_PER_SOL),
+          TOKEN
+        );
         await program.methods
-          .updatePositions(new anchor.BN(0.1 * LAMPORTS_PER_SOL), new anchor.BN(0.1 * LAMPORTS_PER_SOL))
+          .updatePositions(update.creatorValue, update.opponentValue, update.args)
           .accounts({
             duel: pendingDuelPda,
             oracle: oracle.publicKey,
+            instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
           })
+          .preInstructions(update.preInstructions)
           .signers([oracle])
           .rpc();
         
@@ -478,13 +512,8 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(1); // 1 second for quick expiry
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      [settleDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      settleDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       [settleEscrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), settleDuelPda.toBuffer()],
@@ -493,11 +522,12 @@ describe("Trading Duel Protocol", () => {
 
       // Create, accept, and fund the duel
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: settleDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -534,16 +564,21 @@ describe("Trading Duel Protocol", () => {
         .signers([opponent])
         .rpc();
 
+      await initializeOracleTracking(program, settleDuelPda, oracle);
+
       // Update positions
       const creatorValue = new anchor.BN(0.25 * LAMPORTS_PER_SOL); // 25% gain
       const opponentValue = new anchor.BN(0.15 * LAMPORTS_PER_SOL); // 25% loss
 
+      const update = signedPositionUpdate(settleDuelPda, oracle, attestor, creatorValue, opponentValue, TOKEN);
       await program.methods
-        .updatePositions(creatorValue, opponentValue)
+        .updatePositions(update.creatorValue, update.opponentValue, update.args)
         .accounts({
           duel: settleDuelPda,
           oracle: oracle.publicKey,
+          instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
         })
+        .preInstructions(update.preInstructions)
         .signers([oracle])
         .rpc();
 
@@ -604,13 +639,8 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(3600); // 1 hour
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [longDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const longDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       const [longEscrowPda] = PublicKey.findProgramAddressSync(
         [Buffer.from("escrow"), longDuelPda.toBuffer()],
@@ -619,11 +649,12 @@ describe("Trading Duel Protocol", () => {
 
       // Create and start the duel
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: longDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -660,6 +691,8 @@ describe("Trading Duel Protocol", () => {
         .signers([opponent])
         .rpc();
 
+      await initializeOracleTracking(program, longDuelPda, oracle);
+
       // Try to settle before expiry
       try {
         await program.methods
@@ -687,21 +720,17 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(3600);
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [cancelDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const cancelDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       // Create duel
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: cancelDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])
@@ -727,21 +756,17 @@ describe("Trading Duel Protocol", () => {
       const duration = new anchor.BN(3600);
       const allowedTokens: PublicKey[] = [];
 
-      const protocolAccount = await program.account.protocol.fetch(protocolPda);
-      const duelIndex = protocolAccount.totalDuels.toNumber();
-
-      const [acceptedDuelPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("duel"), new anchor.BN(duelIndex).toArrayLike(Buffer, "le", 8)],
-        program.programId
-      );
+      const nonce = newNonce();
+      const acceptedDuelPda = findDuelPda(program.programId, creator.publicKey, nonce);
 
       // Create and accept duel
       await program.methods
-        .createDuel(stakeAmount, duration, allowedTokens)
+        .createDuel(nonce, stakeAmount, duration, allowedTokens, duelArgs())
         .accounts({
           duel: acceptedDuelPda,
           protocol: protocolPda,
           creator: creator.publicKey,
+          treasury: treasury.publicKey,
           systemProgram: SystemProgram.programId,
         })
         .signers([creator])